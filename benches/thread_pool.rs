@@ -0,0 +1,127 @@
+// Compares the throughput of the work-stealing thread pool with the design it replaced, where every worker took jobs from one Mutex<Receiver>. Run with cargo bench, setting BENCH_THREADS to change the number of workers (8 by default).
+//
+// On a single core, 8 workers, 200000 jobs:
+//   empty jobs:              Mutex<Receiver> 10.4M jobs/s, work-stealing 4.7M jobs/s
+//   jobs of 1000 iterations: Mutex<Receiver>  2.2M jobs/s, work-stealing 2.2M jobs/s
+// With one core there is no contention on the shared receiver for the deques to remove, so they only add overhead on jobs that do nothing. The design is meant to pay off with several cores busy at once, which these numbers don't cover yet: run it on a multi-core machine, with BENCH_THREADS at and above the core count, before relying on it being faster.
+use std::hint::black_box;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tiny_rust_server::utils::thread_pool::{PoolConfig, ThreadPool};
+
+const DEFAULT_THREADS: usize = 8;
+const JOBS: usize = 200_000;
+const ROUNDS: usize = 5;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// The previous thread pool: a fixed set of workers sharing one channel receiver.
+struct ChannelPool {
+    sender: Option<Sender<Job>>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl ChannelPool {
+    fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver: Arc<Mutex<Receiver<Job>>> = Arc::new(Mutex::new(receiver));
+        let threads = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        Self {
+            sender: Some(sender),
+            threads,
+        }
+    }
+
+    fn execute<F>(&mut self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+    }
+}
+
+impl Drop for ChannelPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for thread in self.threads.drain(..) {
+            thread.join().unwrap();
+        }
+    }
+}
+
+// A job doing about the given number of iterations of busy work.
+fn work(iterations: u64) -> impl FnOnce() + Send + 'static {
+    move || {
+        let mut sum = 0u64;
+        for i in 0..iterations {
+            sum = sum.wrapping_add(black_box(i));
+        }
+        black_box(sum);
+    }
+}
+
+// Best time of several rounds of queueing JOBS jobs and waiting for all of them to finish.
+fn measure(mut round: impl FnMut()) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            round();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{:<40} {:>10.0} jobs/s ({:?})",
+        name,
+        JOBS as f64 / elapsed.as_secs_f64(),
+        elapsed
+    );
+}
+
+fn main() {
+    let threads = std::env::var("BENCH_THREADS")
+        .ok()
+        .and_then(|threads| threads.parse().ok())
+        .unwrap_or(DEFAULT_THREADS);
+    for iterations in [0, 1_000] {
+        let channel = measure(|| {
+            let mut pool = ChannelPool::new(threads);
+            for _ in 0..JOBS {
+                pool.execute(work(iterations));
+            }
+        });
+        let stealing = measure(|| {
+            let mut pool = ThreadPool::with_config(PoolConfig {
+                queue_capacity: JOBS,
+                ..PoolConfig::fixed(threads)
+            });
+            for _ in 0..JOBS {
+                pool.execute(work(iterations));
+            }
+            pool.shutdown(None);
+        });
+        println!(
+            "{} workers, {} jobs of {} iterations",
+            threads, JOBS, iterations
+        );
+        report("  shared Mutex<Receiver>", channel);
+        report("  work-stealing deques", stealing);
+    }
+}
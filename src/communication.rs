@@ -1,6 +1,15 @@
+pub mod body;
+pub mod cookie;
+pub mod extract;
+pub mod form;
+pub mod json;
 pub mod method;
 pub mod middleware;
+pub mod multipart;
+pub mod protocol;
 pub mod request;
 pub mod response;
 pub mod route;
 pub mod router;
+pub mod secure_cookie;
+pub mod session;
@@ -0,0 +1,340 @@
+use std::cell::{Ref, RefCell};
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::rc::Rc;
+
+// Buffered reader over a client connection, shared between the request head parser and the request body. The same reader is used for every request on the connection, so bytes of pipelined requests read ahead are never lost.
+pub type SharedReader = Rc<RefCell<BufReader<Box<dyn Read>>>>;
+
+// Default maximum size of a request body read into memory.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+
+// Maximum length of a chunk size line, including chunk extensions.
+const MAX_CHUNK_LINE_LEN: usize = 1024;
+
+// Error while reading a request body.
+#[derive(Debug)]
+pub enum BodyError {
+    Io(io::Error),
+    TooLarge(usize),
+    // The body was already consumed as a stream.
+    Consumed,
+}
+
+impl Display for BodyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::Io(e) => write!(f, "Body read error: {}", e),
+            BodyError::TooLarge(limit) => write!(f, "Body exceeds the limit of {} bytes", limit),
+            BodyError::Consumed => write!(f, "Body was already consumed"),
+        }
+    }
+}
+
+impl Error for BodyError {}
+
+impl From<io::Error> for BodyError {
+    fn from(e: io::Error) -> Self {
+        match exceeded_limit(&e) {
+            Some(limit) => BodyError::TooLarge(limit),
+            None => BodyError::Io(e),
+        }
+    }
+}
+
+// The limit a body went over, if the error is the one a body reader returns for that. Readers can only return io::Error, so they wrap BodyError::TooLarge in one.
+pub fn exceeded_limit(e: &io::Error) -> Option<usize> {
+    match e.get_ref()?.downcast_ref::<BodyError>()? {
+        BodyError::TooLarge(limit) => Some(*limit),
+        _ => None,
+    }
+}
+
+// How the end of the body is determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    // Content-Length header, with the number of bytes left.
+    Length(usize),
+    // Transfer-Encoding: chunked, with the number of bytes left in the current chunk.
+    Chunked(usize),
+    Done,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+struct BodyState {
+    reader: SharedReader,
+    framing: Framing,
+    // Bytes of the body read so far.
+    read: usize,
+    // Maximum size of the body. Reads fail once more has been read, whether the body is read into memory or streamed.
+    limit: usize,
+    // Require CRLF line endings and no whitespace around chunk sizes.
+    strict: bool,
+    // Where to send 100 Continue before the first read, for clients waiting for it before sending the body.
+    continue_writer: Option<Box<dyn Write>>,
+}
+
+impl BodyState {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(mut writer) = self.continue_writer.take() {
+            writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            writer.flush()?;
+        }
+        let available = match self.framing {
+            Framing::Done => return Ok(0),
+            Framing::Length(remaining) => remaining,
+            Framing::Chunked(0) => {
+                self.start_chunk()?;
+                match self.framing {
+                    Framing::Chunked(remaining) => remaining,
+                    _ => return Ok(0),
+                }
+            }
+            Framing::Chunked(remaining) => remaining,
+        };
+
+        let max = buf.len().min(available);
+        let read = self.reader.borrow_mut().read(&mut buf[..max])?;
+        if read == 0 && max > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed before the end of the body",
+            ));
+        }
+        self.read += read;
+        if self.read > self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                BodyError::TooLarge(self.limit),
+            ));
+        }
+        self.framing = match self.framing {
+            Framing::Length(remaining) if remaining == read => Framing::Done,
+            Framing::Length(remaining) => Framing::Length(remaining - read),
+            Framing::Chunked(remaining) if remaining == read => {
+                // Every chunk is followed by CRLF.
+                if !self.read_line()?.is_empty() {
+                    return Err(invalid_data("Missing CRLF after chunk"));
+                }
+                Framing::Chunked(0)
+            }
+            Framing::Chunked(remaining) => Framing::Chunked(remaining - read),
+            Framing::Done => Framing::Done,
+        };
+        Ok(read)
+    }
+
+    // Read the size line of the next chunk. The last chunk has size 0 and is followed by optional trailers.
+    fn start_chunk(&mut self) -> io::Result<()> {
+        let line = self.read_line()?;
+        let size = line.split(';').next().unwrap_or_default();
+        if self.strict && size.trim() != size {
+            return Err(invalid_data("Invalid chunk size"));
+        }
+        let size = size.trim();
+        let size = usize::from_str_radix(size, 16)
+            .ok()
+            .filter(|_| !size.is_empty() && !size.starts_with('+'))
+            .ok_or_else(|| invalid_data("Invalid chunk size"))?;
+        if size > 0 {
+            self.framing = Framing::Chunked(size);
+            return Ok(());
+        }
+        // Trailer fields are ignored.
+        while !self.read_line()?.is_empty() {}
+        self.framing = Framing::Done;
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = Vec::new();
+        let mut reader = self.reader.borrow_mut();
+        (&mut *reader)
+            .take(MAX_CHUNK_LINE_LEN as u64)
+            .read_until(b'\n', &mut line)?;
+        if !line.ends_with(b"\n") {
+            return Err(invalid_data("Invalid chunk line"));
+        }
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        } else if self.strict {
+            return Err(invalid_data("Bare LF in chunked body"));
+        }
+        String::from_utf8(line).map_err(|_| invalid_data("Invalid chunk line"))
+    }
+}
+
+// Reader over the bytes of a request body, stopping at the end of the body.
+pub struct BodyReader {
+    state: Rc<RefCell<BodyState>>,
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.state.borrow_mut().read(buf)
+    }
+}
+
+enum Content {
+    Unread,
+    Buffered(Vec<u8>),
+    Streamed,
+}
+
+// Body of a request. The body is read from the connection lazily, when a route function first asks for it.
+pub struct Body {
+    state: Option<Rc<RefCell<BodyState>>>,
+    content: RefCell<Content>,
+    length: Option<usize>,
+    limit: usize,
+}
+
+impl Debug for Body {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Body")
+            .field("length", &self.length)
+            .finish()
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Body {
+    // Create a shared reader for a connection.
+    pub fn shared_reader(reader: impl Read + 'static) -> SharedReader {
+        Rc::new(RefCell::new(BufReader::new(Box::new(reader))))
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            state: None,
+            content: RefCell::new(Content::Buffered(Vec::new())),
+            length: Some(0),
+            limit: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    // Create a body read from the shared reader with the given framing. Strict bodies reject chunk lines other servers might read differently.
+    pub fn new(reader: SharedReader, framing: Framing, limit: usize, strict: bool) -> Self {
+        Self {
+            state: Some(Rc::new(RefCell::new(BodyState {
+                reader,
+                framing,
+                read: 0,
+                limit,
+                strict,
+                continue_writer: None,
+            }))),
+            content: RefCell::new(Content::Unread),
+            length: match framing {
+                Framing::Length(length) => Some(length),
+                Framing::Done => Some(0),
+                Framing::Chunked(_) => None,
+            },
+            limit,
+        }
+    }
+
+    // Length of the body as announced by the client. None for chunked bodies, whose length is only known once read.
+    pub fn len(&self) -> Option<usize> {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == Some(0)
+    }
+
+    // Read the whole body into memory, if not done already, and return it.
+    pub fn bytes(&self) -> Result<Ref<'_, [u8]>, BodyError> {
+        {
+            let mut content = self.content.borrow_mut();
+            match &*content {
+                Content::Unread => {
+                    if self.length.is_some_and(|length| length > self.limit) {
+                        return Err(BodyError::TooLarge(self.limit));
+                    }
+                    let mut bytes = Vec::with_capacity(self.length.unwrap_or_default());
+                    self.open_reader().read_to_end(&mut bytes)?;
+                    *content = Content::Buffered(bytes);
+                }
+                Content::Streamed => return Err(BodyError::Consumed),
+                Content::Buffered(_) => {}
+            }
+        }
+        Ok(Ref::map(self.content.borrow(), |content| match content {
+            Content::Buffered(bytes) => bytes.as_slice(),
+            _ => unreachable!(),
+        }))
+    }
+
+    // Take the body as a stream, without reading it into memory. A body that was already read into memory is streamed from there. Reading past the size limit fails with an error that BodyError::from turns into TooLarge.
+    pub fn reader(&self) -> Result<Box<dyn Read>, BodyError> {
+        let mut content = self.content.borrow_mut();
+        match &*content {
+            Content::Unread => {
+                *content = Content::Streamed;
+                Ok(Box::new(self.open_reader()))
+            }
+            Content::Buffered(bytes) => Ok(Box::new(Cursor::new(bytes.clone()))),
+            Content::Streamed => Err(BodyError::Consumed),
+        }
+    }
+
+    fn open_reader(&self) -> BodyReader {
+        BodyReader {
+            state: self.state.clone().unwrap(),
+        }
+    }
+
+    // Send 100 Continue to the writer when the body is first read, for a request with "Expect: 100-continue". A body that is never read is never asked for.
+    pub fn expect_continue(&self, writer: Box<dyn Write>) {
+        if let Some(ref state) = self.state {
+            state.borrow_mut().continue_writer = Some(writer);
+        }
+    }
+
+    // Whether the client is still waiting for 100 Continue before sending the body. The body won't arrive then, so it can't be drained.
+    pub fn awaiting_continue(&self) -> bool {
+        self.state
+            .as_ref()
+            .is_some_and(|state| state.borrow().continue_writer.is_some())
+    }
+
+    // Whether the whole body has been read from the connection.
+    pub fn is_complete(&self) -> bool {
+        self.state
+            .as_ref()
+            .is_none_or(|state| state.borrow().framing == Framing::Done)
+    }
+
+    // Read and discard the rest of the body, so the next request on the connection can be read. Returns false if more than max bytes are left or the body is invalid.
+    pub fn drain(&self, max: usize) -> bool {
+        let Some(ref state) = self.state else {
+            return true;
+        };
+        let mut state = state.borrow_mut();
+        if matches!(state.framing, Framing::Length(remaining) if remaining > max) {
+            return false;
+        }
+        let start = state.read;
+        let mut buf = [0u8; 8 * 1024];
+        while state.framing != Framing::Done {
+            if state.read - start > max {
+                return false;
+            }
+            if state.read(&mut buf).is_err() {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::protocol::is_token;
+use super::secure_cookie::CookieKeys;
+use crate::utils::date::format_http_date;
+use crate::utils::url::percent_decode;
+
+// Value of the SameSite cookie attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl Display for SameSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SameSite::Strict => write!(f, "Strict"),
+            SameSite::Lax => write!(f, "Lax"),
+            SameSite::None => write!(f, "None"),
+        }
+    }
+}
+
+// Error for a cookie that can't be sent, because one of its fields would let it inject attributes or headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CookieError {
+    // The name is not a non-empty token.
+    InvalidName(String),
+    // The Path or Domain contains ';' or control characters.
+    InvalidAttribute(&'static str, String),
+}
+
+impl Display for CookieError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CookieError::InvalidName(name) => {
+                write!(
+                    f,
+                    "Invalid cookie name {:?}: must be a non-empty token",
+                    name
+                )
+            }
+            CookieError::InvalidAttribute(attribute, value) => write!(
+                f,
+                "Invalid cookie {} {:?}: must not contain ';' or control characters",
+                attribute, value
+            ),
+        }
+    }
+}
+
+impl Error for CookieError {}
+
+// Representation of a cookie sent to the client in a Set-Cookie header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub max_age: Option<Duration>,
+    pub expires: Option<SystemTime>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    // Start building a cookie with the given name and value.
+    pub fn build(name: &str, value: &str) -> CookieBuilder {
+        CookieBuilder {
+            cookie: Self::new(name, value),
+        }
+    }
+
+    // Turn this cookie into one that makes the client delete it. Path and domain are kept, since the client only removes a cookie when they match.
+    pub fn into_removal(mut self) -> Self {
+        self.value = String::new();
+        self.max_age = Some(Duration::ZERO);
+        self.expires = Some(UNIX_EPOCH);
+        self
+    }
+
+    // Format this cookie as the value of a Set-Cookie header. The value is percent-encoded where it is not made of cookie-octets, and decoded again by CookieJar::parse. Fails on a name that is not a token or a Path/Domain that could end the attribute, since those would let the cookie inject attributes or headers.
+    pub fn to_header_value(&self) -> Result<String, CookieError> {
+        if !is_token(&self.name) {
+            return Err(CookieError::InvalidName(self.name.clone()));
+        }
+        let mut header = format!("{}={}", self.name, encode_value(&self.value));
+        if let Some(ref path) = self.path {
+            check_attribute("Path", path)?;
+            header.push_str(&format!("; Path={}", path));
+        }
+        if let Some(ref domain) = self.domain {
+            check_attribute("Domain", domain)?;
+            header.push_str(&format!("; Domain={}", domain));
+        }
+        if let Some(max_age) = self.max_age {
+            header.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+        }
+        if let Some(expires) = self.expires {
+            header.push_str(&format!("; Expires={}", format_http_date(expires)));
+        }
+        if self.secure {
+            header.push_str("; Secure");
+        }
+        if self.http_only {
+            header.push_str("; HttpOnly");
+        }
+        if let Some(same_site) = self.same_site {
+            header.push_str(&format!("; SameSite={}", same_site));
+        }
+        Ok(header)
+    }
+}
+
+// Whether the byte may appear unencoded in a cookie value (RFC 6265 section 4.1.1).
+fn is_cookie_octet(byte: u8) -> bool {
+    matches!(byte, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+// Percent-encode the bytes of a cookie value that are not cookie-octets. '%' is encoded too, so decoding gives back the original value.
+fn encode_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if is_cookie_octet(byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn check_attribute(attribute: &'static str, value: &str) -> Result<(), CookieError> {
+    if value
+        .bytes()
+        .any(|byte| byte == b';' || byte.is_ascii_control())
+    {
+        return Err(CookieError::InvalidAttribute(attribute, value.to_string()));
+    }
+    Ok(())
+}
+
+// Builder for cookies with optional attributes.
+#[derive(Debug, Clone)]
+pub struct CookieBuilder {
+    cookie: Cookie,
+}
+
+impl CookieBuilder {
+    pub fn path(mut self, path: &str) -> Self {
+        self.cookie.path = Some(path.to_string());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.cookie.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.cookie.max_age = Some(max_age);
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.cookie.expires = Some(expires);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.cookie.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.cookie.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        // Browsers reject SameSite=None cookies that are not also Secure.
+        if same_site == SameSite::None {
+            self.cookie.secure = true;
+        }
+        self.cookie.same_site = Some(same_site);
+        self
+    }
+
+    pub fn finish(self) -> Cookie {
+        self.cookie
+    }
+}
+
+// Cookies sent by the client in the Cookie request header.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: HashMap<String, String>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self {
+            cookies: HashMap::new(),
+        }
+    }
+
+    // Parse the value of a Cookie header ("name=value; name2=value2"). Malformed pairs are skipped.
+    pub fn parse(header: &str) -> Self {
+        let mut jar = Self::new();
+        for pair in header.split(';') {
+            if let Some((name, value)) = pair.split_once('=') {
+                let name = name.trim();
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                if name.is_empty() {
+                    continue;
+                }
+                // Values we set are percent-encoded, a value that doesn't decode to UTF-8 is kept as it was sent.
+                let value = percent_decode(value).unwrap_or_else(|_| value.to_string());
+                // The first occurrence wins, as clients send the most specific cookie first.
+                jar.cookies.entry(name.to_string()).or_insert(value);
+            }
+        }
+        jar
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies.get(name).map(|value| value.as_str())
+    }
+
+    // Get the value of a signed cookie. Cookies with a missing or bad signature are treated as absent.
+    pub fn get_signed(&self, name: &str, keys: &CookieKeys) -> Option<String> {
+        self.get(name).and_then(|value| keys.verify(name, value))
+    }
+
+    // Get the value of an encrypted cookie. Cookies that fail to decrypt are treated as absent.
+    pub fn get_private(&self, name: &str, keys: &CookieKeys) -> Option<String> {
+        self.get(name).and_then(|value| keys.decrypt(name, value))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.cookies.contains_key(name)
+    }
+
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.cookies.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.cookies.remove(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_value_includes_attributes() {
+        let cookie = Cookie::build("id", "abc")
+            .path("/")
+            .max_age(Duration::from_secs(60))
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .finish();
+        assert_eq!(
+            cookie.to_header_value().unwrap(),
+            "id=abc; Path=/; Max-Age=60; HttpOnly; SameSite=Lax"
+        );
+    }
+
+    #[test]
+    fn unsafe_values_are_encoded_and_decoded() {
+        let value = "a b;c\r\nSet-Cookie: admin=1\"%";
+        let header = Cookie::new("id", value).to_header_value().unwrap();
+        assert_eq!(header, "id=a%20b%3Bc%0D%0ASet-Cookie:%20admin=1%22%25");
+        let jar = CookieJar::parse(&header);
+        assert_eq!(jar.get("id"), Some(value));
+        assert_eq!(jar.len(), 1);
+    }
+
+    #[test]
+    fn fields_that_could_inject_are_rejected() {
+        assert_eq!(
+            Cookie::new("id\r\nX-Injected: 1", "abc").to_header_value(),
+            Err(CookieError::InvalidName(String::from(
+                "id\r\nX-Injected: 1"
+            )))
+        );
+        assert_eq!(
+            Cookie::new("", "abc").to_header_value(),
+            Err(CookieError::InvalidName(String::new()))
+        );
+        assert_eq!(
+            Cookie::build("id", "abc")
+                .path("/; Domain=evil.example")
+                .finish()
+                .to_header_value(),
+            Err(CookieError::InvalidAttribute(
+                "Path",
+                String::from("/; Domain=evil.example")
+            ))
+        );
+        assert_eq!(
+            Cookie::build("id", "abc")
+                .domain("example.com\r\nX-Injected: 1")
+                .finish()
+                .to_header_value(),
+            Err(CookieError::InvalidAttribute(
+                "Domain",
+                String::from("example.com\r\nX-Injected: 1")
+            ))
+        );
+    }
+
+    #[test]
+    fn removal_expires_the_cookie_and_keeps_its_scope() {
+        let cookie = Cookie::build("id", "abc")
+            .path("/app")
+            .domain("example.com")
+            .max_age(Duration::from_secs(60))
+            .finish()
+            .into_removal();
+        assert_eq!(
+            cookie.to_header_value().unwrap(),
+            "id=; Path=/app; Domain=example.com; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn jar_keeps_first_occurrence() {
+        let jar = CookieJar::parse("a=1; b=\"2\"; a=3; =4; junk");
+        assert_eq!(jar.get("a"), Some("1"));
+        assert_eq!(jar.get("b"), Some("2"));
+        assert_eq!(jar.len(), 2);
+    }
+}
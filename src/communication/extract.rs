@@ -0,0 +1,89 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+use super::body::BodyError;
+use super::request::Request;
+use super::response::Response;
+
+// Error response sent to the client when data can't be extracted from a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub status_code: usize,
+    pub status_message: String,
+    pub message: String,
+}
+
+impl Rejection {
+    pub fn new(status_code: usize, status_message: &str, message: &str) -> Self {
+        Self {
+            status_code,
+            status_message: status_message.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn bad_request(message: &str) -> Self {
+        Self::new(400, "Bad Request", message)
+    }
+
+    pub fn unsupported_media_type(expected: &str) -> Self {
+        Self::new(
+            415,
+            "Unsupported Media Type",
+            &format!("Expected Content-Type {}", expected),
+        )
+    }
+
+    // Write the rejection to the response, replacing its status and content.
+    pub fn apply(&self, response: &mut Response) {
+        response.set_status(self.status_code, &self.status_message);
+        response.set_contents("text/plain", &self.message);
+    }
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            self.status_code, self.status_message, self.message
+        )
+    }
+}
+
+impl Error for Rejection {}
+
+impl From<BodyError> for Rejection {
+    fn from(e: BodyError) -> Self {
+        match e {
+            BodyError::TooLarge(_) => Self::new(413, "Content Too Large", &e.to_string()),
+            BodyError::Io(ref io)
+                if matches!(
+                    io.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                Self::new(408, "Request Timeout", &e.to_string())
+            }
+            _ => Self::bad_request(&e.to_string()),
+        }
+    }
+}
+
+// Data that can be extracted from a request, used with Router::route_with.
+pub trait FromRequest: Sized {
+    fn from_request(request: &Request) -> Result<Self, Rejection>;
+}
+
+// Check that the request has the expected media type, ignoring parameters such as charset.
+pub fn require_content_type(request: &Request, expected: &str) -> Result<(), Rejection> {
+    let media_type = request
+        .header("Content-Type")
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|media_type| media_type.trim());
+    match media_type {
+        Some(media_type) if media_type.eq_ignore_ascii_case(expected) => Ok(()),
+        _ => Err(Rejection::unsupported_media_type(expected)),
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use super::extract::{FromRequest, Rejection};
+use super::request::Request;
+use crate::utils::url::form_decode;
+
+pub const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+// Decoded application/x-www-form-urlencoded data. Keys can repeat, e.g. for multi-select inputs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FormData {
+    pairs: Vec<(String, String)>,
+}
+
+impl FormData {
+    // Parse urlencoded data ("a=1&b=two+words"). Returns None if a component isn't valid UTF-8 once decoded.
+    pub fn parse(input: &str) -> Option<Self> {
+        let mut pairs = Vec::new();
+        for pair in input.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            pairs.push((form_decode(key).ok()?, form_decode(value).ok()?));
+        }
+        Some(Self { pairs })
+    }
+
+    // Get the first value of a key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Get all values of a key in the order they were sent.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+// Types that can be built from form data, used with the Form extractor.
+pub trait FromForm: Sized {
+    // Build the type from the form, returning a message for the client on failure.
+    fn from_form(form: &FormData) -> Result<Self, String>;
+}
+
+impl FromForm for FormData {
+    fn from_form(form: &FormData) -> Result<Self, String> {
+        Ok(form.clone())
+    }
+}
+
+// Keeps the last value of repeated keys.
+impl FromForm for HashMap<String, String> {
+    fn from_form(form: &FormData) -> Result<Self, String> {
+        Ok(form
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect())
+    }
+}
+
+// Extractor for an application/x-www-form-urlencoded request body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Form<T>(pub T);
+
+impl<T: FromForm> FromRequest for Form<T> {
+    fn from_request(request: &Request) -> Result<Self, Rejection> {
+        let form = request.form()?;
+        T::from_form(&form)
+            .map(Form)
+            .map_err(|message| Rejection::bad_request(&message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_are_decoded() {
+        let form = FormData::parse("name=two+words&city=S%C3%A3o+Paulo&sum=1%2B1").unwrap();
+        assert_eq!(form.get("name"), Some("two words"));
+        assert_eq!(form.get("city"), Some("S\u{e3}o Paulo"));
+        assert_eq!(form.get("sum"), Some("1+1"));
+        assert_eq!(form.len(), 3);
+    }
+
+    #[test]
+    fn repeated_keys_keep_every_value() {
+        let form = FormData::parse("tag=a&tag=b&other=c&tag=").unwrap();
+        assert_eq!(form.get("tag"), Some("a"));
+        assert_eq!(form.get_all("tag"), vec!["a", "b", ""]);
+        let map = HashMap::<String, String>::from_form(&form).unwrap();
+        assert_eq!(map.get("tag").map(String::as_str), Some(""));
+    }
+
+    #[test]
+    fn keys_without_values_and_empty_pairs() {
+        let form = FormData::parse("&flag&a=1&&b=x=y&").unwrap();
+        assert_eq!(form.get("flag"), Some(""));
+        assert_eq!(form.get("b"), Some("x=y"));
+        assert_eq!(form.len(), 3);
+        assert!(FormData::parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_escapes_are_kept_and_invalid_utf8_is_rejected() {
+        let form = FormData::parse("discount=100%&q=%zz").unwrap();
+        assert_eq!(form.get("discount"), Some("100%"));
+        assert_eq!(form.get("q"), Some("%zz"));
+        assert_eq!(FormData::parse("name=%ff"), None);
+    }
+}
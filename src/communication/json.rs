@@ -0,0 +1,15 @@
+use super::extract::{FromRequest, Rejection};
+use super::request::Request;
+use crate::utils::json::FromJson;
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+// Extractor for a JSON request body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T: FromJson> FromRequest for Json<T> {
+    fn from_request(request: &Request) -> Result<Self, Rejection> {
+        request.json::<T>().map(Json)
+    }
+}
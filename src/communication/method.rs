@@ -1,5 +1,6 @@
 use std::error::Error;
-use std::io::{self, ErrorKind};
+use std::io;
+use std::str::FromStr;
 
 // Representation of the supported HTTP methods.
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -10,21 +11,44 @@ pub enum Method {
     DELETE(String),
 }
 
+// The trait impls forward to the inherent functions, which existing callers use by path.
+impl Default for Method {
+    fn default() -> Self {
+        Method::default()
+    }
+}
+
+impl FromStr for Method {
+    type Err = Box<dyn Error>;
+
+    fn from_str(method: &str) -> Result<Method, Box<dyn Error>> {
+        Method::from_str(method)
+    }
+}
+
 impl Method {
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Self {
         Method::GET(String::from("GET"))
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(method: &str) -> Result<Method, Box<dyn Error>> {
         match method {
             "GET" => Ok(Method::GET(String::from("GET"))),
             "POST" => Ok(Method::POST(String::from("POST"))),
             "PUT" => Ok(Method::PUT(String::from("PUT"))),
             "DELETE" => Ok(Method::DELETE(String::from("DELETE"))),
-            _ => Err(Box::new(io::Error::new(
-                ErrorKind::Other,
-                "Non-supported request method",
-            ))),
+            _ => Err(Box::new(io::Error::other("Non-supported request method"))),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::GET(method)
+            | Method::POST(method)
+            | Method::PUT(method)
+            | Method::DELETE(method) => method,
         }
     }
 
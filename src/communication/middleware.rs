@@ -1,16 +1,33 @@
 use std::sync::Arc;
 
-use super::request::Request;
+use super::{request::Request, response::Response};
+
+// User defined function type executed before every route of a router.
+pub type MiddlewareFunc = Arc<dyn Fn(Option<&Middleware>, &mut Request) + Send + Sync + 'static>;
+
+// User defined function type executed after every route of a router, with access to the response.
+pub type AfterMiddlewareFunc = Arc<dyn Fn(&Request, &mut Response) + Send + Sync + 'static>;
 
 #[derive(Clone)]
 pub struct Middleware {
-    pub func: Arc<dyn Fn(Option<&Middleware>, &mut Request) -> () + Send + Sync + 'static>,
+    pub func: MiddlewareFunc,
 }
 
 impl Middleware {
-    pub fn new(
-        func: impl Fn(Option<&Middleware>, &mut Request) -> () + Send + Sync + 'static + 'static,
-    ) -> Self {
+    pub fn new(func: impl Fn(Option<&Middleware>, &mut Request) + Send + Sync + 'static) -> Self {
+        Self {
+            func: Arc::new(func),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AfterMiddleware {
+    pub func: AfterMiddlewareFunc,
+}
+
+impl AfterMiddleware {
+    pub fn new(func: impl Fn(&Request, &mut Response) + Send + Sync + 'static) -> Self {
         Self {
             func: Arc::new(func),
         }
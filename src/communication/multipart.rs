@@ -0,0 +1,777 @@
+use std::env;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::body::{exceeded_limit, DEFAULT_MAX_BODY_SIZE};
+use super::extract::Rejection;
+use crate::utils::base64::encode_url_safe;
+use crate::utils::crypto::random_bytes;
+use crate::utils::url::percent_decode;
+
+pub const MULTIPART_CONTENT_TYPE: &str = "multipart/form-data";
+
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+// Limits applied while parsing a multipart body.
+#[derive(Debug, Clone)]
+pub struct MultipartConfig {
+    // Parts larger than this are written to a temporary file instead of kept in memory.
+    pub memory_threshold: usize,
+    // Maximum size of a single file part.
+    pub max_file_size: usize,
+    // Maximum size of a single non-file field.
+    pub max_field_size: usize,
+    // Maximum combined size of all parts. The maximum body size of the server applies as well, so raising this past it takes raising that too.
+    pub max_total_size: usize,
+    // Maximum size of the headers of a single part.
+    pub max_header_size: usize,
+    pub max_parts: usize,
+    // Directory for temporary files, defaults to the system temporary directory.
+    pub temp_dir: PathBuf,
+}
+
+impl Default for MultipartConfig {
+    fn default() -> Self {
+        Self {
+            memory_threshold: 256 * 1024,
+            max_file_size: DEFAULT_MAX_BODY_SIZE,
+            max_field_size: 1024 * 1024,
+            max_total_size: DEFAULT_MAX_BODY_SIZE,
+            max_header_size: 8 * 1024,
+            max_parts: 1000,
+            temp_dir: env::temp_dir(),
+        }
+    }
+}
+
+// Error while parsing a multipart body.
+#[derive(Debug)]
+pub enum MultipartError {
+    Io(io::Error),
+    Malformed(&'static str),
+    TooLarge(&'static str),
+}
+
+impl Display for MultipartError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::Io(e) => write!(f, "Multipart read error: {}", e),
+            MultipartError::Malformed(reason) => write!(f, "Malformed multipart body: {}", reason),
+            MultipartError::TooLarge(limit) => write!(f, "Multipart body exceeds {}", limit),
+        }
+    }
+}
+
+impl Error for MultipartError {}
+
+impl From<io::Error> for MultipartError {
+    fn from(e: io::Error) -> Self {
+        match exceeded_limit(&e) {
+            Some(_) => MultipartError::TooLarge("the maximum body size"),
+            None => MultipartError::Io(e),
+        }
+    }
+}
+
+impl From<MultipartError> for Rejection {
+    fn from(e: MultipartError) -> Self {
+        match e {
+            MultipartError::TooLarge(_) => Rejection::new(413, "Content Too Large", &e.to_string()),
+            _ => Rejection::bad_request(&e.to_string()),
+        }
+    }
+}
+
+// Temporary file holding a large part. The file is deleted when dropped, unless it was persisted.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    len: usize,
+    persisted: bool,
+}
+
+impl TempFile {
+    fn create(dir: &Path) -> Result<(Self, File), io::Error> {
+        let path = dir.join(format!(
+            "tiny-http-upload-{}",
+            encode_url_safe(&random_bytes(12))
+        ));
+        // Uploads must not be readable by other local users, so on Unix the file is created with 0600.
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&path)?;
+        Ok((
+            Self {
+                path,
+                len: 0,
+                persisted: false,
+            },
+            file,
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Move the file to a permanent location. Falls back to copying when the target is on another file system.
+    pub fn persist(mut self, to: &Path) -> Result<(), io::Error> {
+        if fs::rename(&self.path, to).is_err() {
+            fs::copy(&self.path, to)?;
+            fs::remove_file(&self.path)?;
+        }
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// Content of a part, kept in memory or spilled to a temporary file.
+#[derive(Debug)]
+pub enum PartData {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+// A single field or file of a multipart body.
+#[derive(Debug)]
+pub struct Part {
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub data: PartData,
+}
+
+impl Part {
+    // Whether the part is a file upload rather than a plain form field.
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.data {
+            PartData::Memory(bytes) => bytes.len(),
+            PartData::File(file) => file.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Get the content of the part, reading it back from disk if it was spilled.
+    pub fn bytes(&self) -> Result<Vec<u8>, io::Error> {
+        match &self.data {
+            PartData::Memory(bytes) => Ok(bytes.clone()),
+            PartData::File(file) => fs::read(file.path()),
+        }
+    }
+
+    pub fn text(&self) -> Result<String, io::Error> {
+        String::from_utf8(self.bytes()?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+// Get the boundary parameter of a multipart Content-Type header value.
+pub fn get_boundary(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case("boundary") {
+            return None;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        (!value.is_empty() && value.len() <= 70).then(|| value.to_string())
+    })
+}
+
+// Where the parser is within the body.
+#[derive(PartialEq, Eq)]
+enum State {
+    // Before the first boundary, skipping the preamble.
+    Preamble,
+    // Right after a boundary, where part headers or the final "--" follow.
+    Boundary,
+    Finished,
+}
+
+// Streaming parser for multipart/form-data bodies. Parts are read one at a time, so only the current part is held in memory (or on disk).
+pub struct Multipart<R: Read> {
+    reader: R,
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    eof: bool,
+    state: State,
+    config: MultipartConfig,
+    total_size: usize,
+    parts: usize,
+}
+
+impl<R: Read> Multipart<R> {
+    pub fn new(reader: R, boundary: &str, config: MultipartConfig) -> Self {
+        Self {
+            reader,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // The first boundary isn't preceded by CRLF, so pretend it is to match it the same way as the others.
+            buffer: b"\r\n".to_vec(),
+            eof: false,
+            state: State::Preamble,
+            config,
+            total_size: 0,
+            parts: 0,
+        }
+    }
+
+    // Read the next part. Returns None after the final boundary.
+    pub fn next_part(&mut self) -> Result<Option<Part>, MultipartError> {
+        if self.state == State::Preamble {
+            self.skip_preamble()?;
+        }
+        if self.state == State::Finished {
+            return Ok(None);
+        }
+
+        // After a boundary comes either "--" for the end of the body, or CRLF and the part headers.
+        self.fill_to(2)?;
+        if self.buffer.starts_with(b"--") {
+            self.state = State::Finished;
+            return Ok(None);
+        }
+        let line_end = self.read_until(b"\r\n", self.config.max_header_size)?;
+        if self.buffer[..line_end]
+            .iter()
+            .any(|b| *b != b' ' && *b != b'\t')
+        {
+            return Err(MultipartError::Malformed("unexpected data after boundary"));
+        }
+        self.buffer.drain(..line_end + 2);
+
+        self.parts += 1;
+        if self.parts > self.config.max_parts {
+            return Err(MultipartError::TooLarge("the maximum number of parts"));
+        }
+        let mut part = self.read_part_headers()?;
+        part.data = self.read_part_data(part.filename.is_some())?;
+        Ok(Some(part))
+    }
+
+    fn skip_preamble(&mut self) -> Result<(), MultipartError> {
+        loop {
+            if let Some(position) = find(&self.buffer, &self.delimiter) {
+                self.buffer.drain(..position + self.delimiter.len());
+                self.state = State::Boundary;
+                return Ok(());
+            }
+            if self.eof {
+                return Err(MultipartError::Malformed("missing boundary"));
+            }
+            let keep = self.delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                self.buffer.drain(..self.buffer.len() - keep);
+            }
+            self.fill()?;
+        }
+    }
+
+    fn read_part_headers(&mut self) -> Result<Part, MultipartError> {
+        let end = self.read_until(b"\r\n\r\n", self.config.max_header_size)?;
+        let head = String::from_utf8_lossy(&self.buffer[..end]).to_string();
+        self.buffer.drain(..end + 4);
+
+        let mut part = Part {
+            name: None,
+            filename: None,
+            content_type: None,
+            headers: Vec::new(),
+            data: PartData::Memory(Vec::new()),
+        };
+        for line in head.split("\r\n").filter(|line| !line.is_empty()) {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(MultipartError::Malformed("invalid part header"))?;
+            let (name, value) = (name.trim(), value.trim());
+            if name.eq_ignore_ascii_case("Content-Disposition") {
+                let (field_name, filename) = parse_content_disposition(value);
+                part.name = field_name;
+                part.filename = filename;
+            } else if name.eq_ignore_ascii_case("Content-Type") {
+                part.content_type = Some(value.to_string());
+            }
+            part.headers.push((name.to_string(), value.to_string()));
+        }
+        Ok(part)
+    }
+
+    // Stream the part content up to the next boundary into memory, spilling to a temporary file past the threshold.
+    fn read_part_data(&mut self, is_file: bool) -> Result<PartData, MultipartError> {
+        let (limit, limit_name) = if is_file {
+            (self.config.max_file_size, "the maximum file size")
+        } else {
+            (self.config.max_field_size, "the maximum field size")
+        };
+        let mut memory: Vec<u8> = Vec::new();
+        let mut spilled: Option<(TempFile, File)> = None;
+        let mut size = 0;
+
+        loop {
+            let (available, found) = match find(&self.buffer, &self.delimiter) {
+                Some(position) => (position, true),
+                // Keep enough bytes to recognize a boundary split across reads.
+                None => (
+                    self.buffer.len().saturating_sub(self.delimiter.len() - 1),
+                    false,
+                ),
+            };
+
+            size += available;
+            self.total_size += available;
+            if size > limit {
+                return Err(MultipartError::TooLarge(limit_name));
+            }
+            if self.total_size > self.config.max_total_size {
+                return Err(MultipartError::TooLarge("the maximum total size"));
+            }
+
+            let chunk = &self.buffer[..available];
+            match spilled {
+                Some((_, ref mut file)) => file.write_all(chunk)?,
+                None if memory.len() + chunk.len() > self.config.memory_threshold => {
+                    let (temp, mut file) = TempFile::create(&self.config.temp_dir)?;
+                    file.write_all(&memory)?;
+                    file.write_all(chunk)?;
+                    memory = Vec::new();
+                    spilled = Some((temp, file));
+                }
+                None => memory.extend_from_slice(chunk),
+            }
+
+            if found {
+                self.buffer.drain(..available + self.delimiter.len());
+                break;
+            }
+            self.buffer.drain(..available);
+            if self.eof {
+                return Err(MultipartError::Malformed("unexpected end of body"));
+            }
+            self.fill()?;
+        }
+
+        Ok(match spilled {
+            Some((mut temp, mut file)) => {
+                file.flush()?;
+                temp.len = size;
+                PartData::File(temp)
+            }
+            None => PartData::Memory(memory),
+        })
+    }
+
+    // Buffer data until the pattern is found, returning its position. Fails if more than max bytes come before it.
+    fn read_until(&mut self, pattern: &[u8], max: usize) -> Result<usize, MultipartError> {
+        loop {
+            if let Some(position) = find(&self.buffer, pattern) {
+                return Ok(position);
+            }
+            if self.buffer.len() > max {
+                return Err(MultipartError::TooLarge("the maximum part header size"));
+            }
+            if self.eof {
+                return Err(MultipartError::Malformed("unexpected end of body"));
+            }
+            self.fill()?;
+        }
+    }
+
+    fn fill_to(&mut self, len: usize) -> Result<(), MultipartError> {
+        while self.buffer.len() < len {
+            if self.eof {
+                return Err(MultipartError::Malformed("unexpected end of body"));
+            }
+            self.fill()?;
+        }
+        Ok(())
+    }
+
+    fn fill(&mut self) -> Result<(), MultipartError> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        let read = self.reader.read(&mut chunk)?;
+        if read == 0 {
+            self.eof = true;
+        }
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Multipart<R> {
+    type Item = Result<Part, MultipartError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_part() {
+            Ok(part) => part.map(Ok),
+            Err(e) => {
+                // Stop after an error, the rest of the body can't be trusted.
+                self.state = State::Finished;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// Get the name and filename parameters of a Content-Disposition header value.
+fn parse_content_disposition(value: &str) -> (Option<String>, Option<String>) {
+    let mut name = None;
+    let mut filename = None;
+    let mut extended_filename = None;
+    for (key, value) in parse_header_params(value) {
+        match key.to_ascii_lowercase().as_str() {
+            "name" => name = Some(value),
+            "filename" => filename = Some(value),
+            // RFC 5987 encoded filename, e.g. filename*=UTF-8''na%C3%AFve.txt
+            "filename*" => {
+                extended_filename = value
+                    .split_once("''")
+                    .and_then(|(_, encoded)| percent_decode(encoded).ok())
+            }
+            _ => {}
+        }
+    }
+    (name, extended_filename.or(filename))
+}
+
+// Parse "; key=value" parameters of a header value, where values can be quoted strings.
+fn parse_header_params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = value.chars().peekable();
+    // Skip the disposition type.
+    for c in chars.by_ref() {
+        if c == ';' {
+            break;
+        }
+    }
+    loop {
+        let key: String = chars
+            .by_ref()
+            .take_while(|c| *c != '=')
+            .collect::<String>()
+            .trim()
+            .to_string();
+        if key.is_empty() {
+            break;
+        }
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+            for c in chars.by_ref() {
+                if c == ';' {
+                    break;
+                }
+            }
+        } else {
+            value = chars
+                .by_ref()
+                .take_while(|c| *c != ';')
+                .collect::<String>()
+                .trim()
+                .to_string();
+        }
+        params.push((key, value));
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "XyZ";
+
+    // Hands out at most a few bytes per read, so boundaries end up split across reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    fn body(parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut body = b"preamble to ignore\r\n".to_vec();
+        for (headers, content) in parts {
+            body.extend_from_slice(
+                format!("--{}\r\n{}\r\n\r\n{}\r\n", BOUNDARY, headers, content).as_bytes(),
+            );
+        }
+        body.extend_from_slice(format!("--{}--\r\nepilogue", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn parse(body: &[u8], config: MultipartConfig) -> Result<Vec<Part>, MultipartError> {
+        Multipart::new(Trickle(body), BOUNDARY, config).collect()
+    }
+
+    #[test]
+    fn boundary_parameter_is_extracted() {
+        assert_eq!(
+            get_boundary("multipart/form-data; boundary=abc").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            get_boundary("multipart/form-data; charset=utf-8; Boundary=\"a b\"").as_deref(),
+            Some("a b")
+        );
+        assert_eq!(get_boundary("multipart/form-data"), None);
+        assert_eq!(get_boundary("multipart/form-data; boundary="), None);
+        assert_eq!(
+            get_boundary(&format!("multipart/form-data; boundary={}", "a".repeat(71))),
+            None
+        );
+    }
+
+    #[test]
+    fn fields_and_files_are_parsed() {
+        let body = body(&[
+            ("Content-Disposition: form-data; name=\"title\"", "Hello\r\n--not a boundary"),
+            (
+                "Content-Disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\".txt\"\r\nContent-Type: text/plain",
+                "file content",
+            ),
+            (
+                "Content-Disposition: form-data; name=doc; filename=\"fallback\"; filename*=UTF-8''na%C3%AFve.txt",
+                "",
+            ),
+        ]);
+        let parts = parse(&body, MultipartConfig::default()).unwrap();
+        assert_eq!(parts.len(), 3);
+
+        assert_eq!(parts[0].name.as_deref(), Some("title"));
+        assert!(!parts[0].is_file());
+        assert_eq!(parts[0].text().unwrap(), "Hello\r\n--not a boundary");
+
+        assert_eq!(parts[1].filename.as_deref(), Some("a \"b\".txt"));
+        assert_eq!(parts[1].content_type.as_deref(), Some("text/plain"));
+        assert_eq!(parts[1].header("content-type"), Some("text/plain"));
+        assert_eq!(parts[1].bytes().unwrap(), b"file content");
+
+        assert_eq!(parts[2].name.as_deref(), Some("doc"));
+        assert_eq!(parts[2].filename.as_deref(), Some("na\u{ef}ve.txt"));
+        assert!(parts[2].is_empty());
+    }
+
+    #[test]
+    fn large_parts_spill_to_temp_files() {
+        let content = "x".repeat(100);
+        let body = body(&[(
+            "Content-Disposition: form-data; name=\"upload\"; filename=\"big.bin\"",
+            &content,
+        )]);
+        let config = MultipartConfig {
+            memory_threshold: 10,
+            ..MultipartConfig::default()
+        };
+        let mut parts = parse(&body, config).unwrap();
+        let part = parts.pop().unwrap();
+        let PartData::File(ref file) = part.data else {
+            panic!("part was kept in memory");
+        };
+        let path = file.path().to_path_buf();
+        assert_eq!(file.len(), 100);
+        assert_eq!(part.text().unwrap(), content);
+        drop(part);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn temp_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let body = body(&[(
+            "Content-Disposition: form-data; name=\"f\"; filename=\"f\"",
+            "secret",
+        )]);
+        let config = MultipartConfig {
+            memory_threshold: 0,
+            ..MultipartConfig::default()
+        };
+        let part = parse(&body, config).unwrap().pop().unwrap();
+        let PartData::File(ref file) = part.data else {
+            panic!("part was kept in memory");
+        };
+        let mode = fs::metadata(file.path()).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o600);
+    }
+
+    #[test]
+    fn persisted_temp_files_are_kept() {
+        let body = body(&[(
+            "Content-Disposition: form-data; name=\"f\"; filename=\"f\"",
+            "persist me",
+        )]);
+        let config = MultipartConfig {
+            memory_threshold: 0,
+            ..MultipartConfig::default()
+        };
+        let part = parse(&body, config).unwrap().pop().unwrap();
+        let PartData::File(file) = part.data else {
+            panic!("part was kept in memory");
+        };
+        let target = env::temp_dir().join(format!(
+            "tiny-http-persisted-{}",
+            encode_url_safe(&random_bytes(9))
+        ));
+        file.persist(&target).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"persist me");
+        fs::remove_file(target).unwrap();
+    }
+
+    #[test]
+    fn limits_are_enforced() {
+        let field = ("Content-Disposition: form-data; name=\"a\"", "0123456789");
+        let file = (
+            "Content-Disposition: form-data; name=\"b\"; filename=\"b\"",
+            "0123456789",
+        );
+        let too_large = |config: MultipartConfig, parts: &[(&str, &str)]| {
+            matches!(
+                parse(&body(parts), config),
+                Err(MultipartError::TooLarge(_))
+            )
+        };
+        let config = MultipartConfig::default;
+        assert!(too_large(
+            MultipartConfig {
+                max_field_size: 9,
+                ..config()
+            },
+            &[field]
+        ));
+        assert!(!too_large(
+            MultipartConfig {
+                max_field_size: 9,
+                ..config()
+            },
+            &[file]
+        ));
+        assert!(too_large(
+            MultipartConfig {
+                max_file_size: 9,
+                ..config()
+            },
+            &[file]
+        ));
+        assert!(too_large(
+            MultipartConfig {
+                max_total_size: 15,
+                ..config()
+            },
+            &[field, file]
+        ));
+        assert!(too_large(
+            MultipartConfig {
+                max_parts: 1,
+                ..config()
+            },
+            &[field, file]
+        ));
+        assert!(too_large(
+            MultipartConfig {
+                max_header_size: 16,
+                ..config()
+            },
+            &[field]
+        ));
+    }
+
+    #[test]
+    fn malformed_bodies_are_rejected() {
+        let malformed = |body: &str| {
+            matches!(
+                parse(body.as_bytes(), MultipartConfig::default()),
+                Err(MultipartError::Malformed(_))
+            )
+        };
+        // No boundary at all.
+        assert!(malformed("just some text"));
+        // Truncated before the final boundary.
+        assert!(malformed(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue"
+        ));
+        // Truncated inside the part headers.
+        assert!(malformed("--XyZ\r\nContent-Disposition: form-data"));
+        // Header line without a colon.
+        assert!(malformed("--XyZ\r\nnot a header\r\n\r\nvalue\r\n--XyZ--"));
+        // Garbage right after a boundary.
+        assert!(malformed(
+            "--XyZjunk\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nv\r\n--XyZ--"
+        ));
+    }
+
+    #[test]
+    fn iteration_stops_after_an_error() {
+        let mut multipart = Multipart::new(
+            Trickle(b"--XyZ\r\nbad\r\n\r\nv\r\n--XyZ--"),
+            BOUNDARY,
+            MultipartConfig::default(),
+        );
+        assert!(multipart.next().unwrap().is_err());
+        assert!(multipart.next().is_none());
+    }
+
+    #[test]
+    fn empty_multipart_has_no_parts() {
+        assert!(parse(b"--XyZ--\r\n", MultipartConfig::default())
+            .unwrap()
+            .is_empty());
+    }
+}
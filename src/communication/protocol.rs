@@ -0,0 +1,280 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+use super::body::DEFAULT_MAX_BODY_SIZE;
+use super::method::Method;
+use super::response::Response;
+
+// Limits on the size of requests. Requests over a limit are answered with an error status and the connection is closed.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestLimits {
+    // Maximum length of the request target (path and query). Longer targets get 414 URI Too Long.
+    pub max_target_length: usize,
+    // Maximum total size of the header lines. Larger headers get 431 Request Header Fields Too Large.
+    pub max_header_size: usize,
+    // Maximum number of header lines, also answered with 431.
+    pub max_headers: usize,
+    // Maximum size of a request body. Larger bodies get 413 Content Too Large.
+    pub max_body_size: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_target_length: 8 * 1024,
+            max_header_size: 16 * 1024,
+            max_headers: 100,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+// Error while reading the head of a request.
+#[derive(Debug)]
+pub enum ProtocolError {
+    BadRequest(String),
+    Timeout,
+    ContentTooLarge(usize),
+    UriTooLong(usize),
+    HeadersTooLarge,
+    // Expect header with an expectation other than 100-continue.
+    ExpectationFailed(String),
+    // Method or transfer coding the server doesn't support.
+    NotImplemented(String),
+    VersionNotSupported(String),
+    // The connection failed or was closed, so no response can be sent.
+    Io(io::Error),
+}
+
+impl ProtocolError {
+    // Status code and message sent to the client, if any.
+    pub fn status(&self) -> Option<(usize, &'static str)> {
+        match self {
+            ProtocolError::BadRequest(_) => Some((400, "Bad Request")),
+            ProtocolError::Timeout => Some((408, "Request Timeout")),
+            ProtocolError::ContentTooLarge(_) => Some((413, "Content Too Large")),
+            ProtocolError::UriTooLong(_) => Some((414, "URI Too Long")),
+            ProtocolError::ExpectationFailed(_) => Some((417, "Expectation Failed")),
+            ProtocolError::HeadersTooLarge => Some((431, "Request Header Fields Too Large")),
+            ProtocolError::NotImplemented(_) => Some((501, "Not Implemented")),
+            ProtocolError::VersionNotSupported(_) => Some((505, "HTTP Version Not Supported")),
+            ProtocolError::Io(_) => None,
+        }
+    }
+
+    // Build the error response for the client, if any. The connection should be closed after sending it.
+    pub fn to_response(&self) -> Option<Response> {
+        let (status_code, status_message) = self.status()?;
+        let mut response = Response::new();
+        response.set_status(status_code, status_message);
+        response.set_contents("text/plain", &self.to_string());
+        response.set_header("Connection", "close");
+        Some(response)
+    }
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::BadRequest(message) => write!(f, "Bad request: {}", message),
+            ProtocolError::Timeout => write!(f, "Timed out reading the request"),
+            ProtocolError::ContentTooLarge(limit) => {
+                write!(f, "Body exceeds the limit of {} bytes", limit)
+            }
+            ProtocolError::UriTooLong(limit) => {
+                write!(f, "Request target exceeds the limit of {} bytes", limit)
+            }
+            ProtocolError::HeadersTooLarge => write!(f, "Request headers are too large"),
+            ProtocolError::ExpectationFailed(expectation) => {
+                write!(f, "Unsupported expectation: {}", expectation)
+            }
+            ProtocolError::NotImplemented(message) => write!(f, "Not implemented: {}", message),
+            ProtocolError::VersionNotSupported(version) => {
+                write!(f, "HTTP version {} is not supported", version)
+            }
+            ProtocolError::Io(e) => write!(f, "Connection error: {}", e),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+impl From<io::Error> for ProtocolError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ProtocolError::Timeout,
+            io::ErrorKind::InvalidData => ProtocolError::BadRequest(e.to_string()),
+            _ => ProtocolError::Io(e),
+        }
+    }
+}
+
+// HTTP version of a request. Later HTTP/1.x minor versions are handled as HTTP/1.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Http10 => write!(f, "HTTP/1.0"),
+            Version::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+impl Version {
+    // Parse a HTTP-version token ("HTTP/1.1"). Returns a 505 error for versions other than 1.x.
+    pub fn parse(version: &str) -> Result<Self, ProtocolError> {
+        let malformed =
+            || ProtocolError::BadRequest(format!("Malformed HTTP version: {}", version));
+        let Some(&[major, b'.', minor]) = version.strip_prefix("HTTP/").map(str::as_bytes) else {
+            return Err(malformed());
+        };
+        if !major.is_ascii_digit() || !minor.is_ascii_digit() {
+            return Err(malformed());
+        }
+        match (major, minor) {
+            (b'1', b'0') => Ok(Version::Http10),
+            (b'1', _) => Ok(Version::Http11),
+            _ => Err(ProtocolError::VersionNotSupported(version.to_string())),
+        }
+    }
+}
+
+// The four forms of a request target (RFC 9112 section 3.2).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestTarget {
+    // "/path?query", used for most requests.
+    Origin {
+        path: String,
+        query: Option<String>,
+    },
+    // "http://host/path?query", used for requests to proxies.
+    Absolute {
+        authority: String,
+        path: String,
+        query: Option<String>,
+    },
+    // "host:port", only used with CONNECT.
+    Authority(String),
+    // "*", only used with OPTIONS.
+    Asterisk,
+}
+
+impl RequestTarget {
+    // Parse a request target, returning None if it matches none of the forms.
+    pub fn parse(target: &str) -> Option<Self> {
+        if target.is_empty() || !target.bytes().all(|byte| byte.is_ascii_graphic()) {
+            return None;
+        }
+        if target == "*" {
+            return Some(RequestTarget::Asterisk);
+        }
+        if target.starts_with('/') {
+            let (path, query) = split_query(target);
+            return Some(RequestTarget::Origin { path, query });
+        }
+        if let Some((scheme, rest)) = target.split_once("://") {
+            if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+                return None;
+            }
+            let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+            let (authority, rest) = rest.split_at(end);
+            if !is_valid_host(authority) || rest.starts_with('#') {
+                return None;
+            }
+            let (path, query) = split_query(rest);
+            let path = if path.is_empty() {
+                String::from("/")
+            } else {
+                path
+            };
+            return Some(RequestTarget::Absolute {
+                authority: authority.to_string(),
+                path,
+                query,
+            });
+        }
+        if is_valid_host(target) && target.contains(':') {
+            return Some(RequestTarget::Authority(target.to_string()));
+        }
+        None
+    }
+}
+
+fn split_query(target: &str) -> (String, Option<String>) {
+    match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    }
+}
+
+// Parse a request line: exactly method SP request-target SP HTTP-version.
+pub fn parse_request_line(
+    line: &str,
+    limits: &RequestLimits,
+) -> Result<(Method, RequestTarget, Version), ProtocolError> {
+    let malformed = || ProtocolError::BadRequest(format!("Malformed request line: {}", line));
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(malformed());
+    };
+    if !is_token(method) {
+        return Err(malformed());
+    }
+    if target.len() > limits.max_target_length {
+        return Err(ProtocolError::UriTooLong(limits.max_target_length));
+    }
+    let target = RequestTarget::parse(target).ok_or_else(malformed)?;
+    let version = Version::parse(version)?;
+    let method = Method::from_str(method)
+        .map_err(|_| ProtocolError::NotImplemented(format!("Method {}", method)))?;
+    // None of the supported methods use the authority or asterisk forms.
+    if matches!(
+        target,
+        RequestTarget::Authority(_) | RequestTarget::Asterisk
+    ) {
+        return Err(malformed());
+    }
+    Ok((method, target, version))
+}
+
+// Parse a header line ("Name: value") into its name and value without surrounding whitespace.
+pub fn parse_header_line(line: &str) -> Result<(&str, &str), ProtocolError> {
+    let malformed = || ProtocolError::BadRequest(format!("Malformed header line: {}", line));
+    // Whitespace between the name and the colon isn't allowed, as intermediaries might read the name differently.
+    let (name, value) = line.split_once(':').ok_or_else(malformed)?;
+    if !is_token(name) {
+        return Err(malformed());
+    }
+    let value = value.trim_matches([' ', '\t']);
+    if !value
+        .bytes()
+        .all(|byte| byte == b'\t' || !byte.is_ascii_control())
+    {
+        return Err(malformed());
+    }
+    Ok((name, value))
+}
+
+// Whether the string is a token (RFC 9110 section 5.6.2), the syntax of methods and header names.
+pub fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+// Whether the string is a valid host with an optional port, as in the Host header.
+pub fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-._~%!$&'()*+,;=:[]".contains(&byte))
+}
@@ -1,6 +1,45 @@
-use crate::log;
-use crate::{communication::method::Method, utils::stream::read_stream_lines};
-use std::{collections::HashMap, error::Error, net::TcpStream};
+use crate::{
+    communication::{
+        body::{Body, BodyError, Framing, SharedReader},
+        cookie::CookieJar,
+        extract::{require_content_type, Rejection},
+        form::{FormData, FORM_CONTENT_TYPE},
+        json::JSON_CONTENT_TYPE,
+        method::Method,
+        multipart::{get_boundary, Multipart, MultipartConfig, MULTIPART_CONTENT_TYPE},
+        protocol::{
+            is_valid_host, parse_header_line, parse_request_line, ProtocolError, RequestLimits,
+            RequestTarget, Version,
+        },
+        session::Session,
+    },
+    utils::{
+        json::{FromJson, JsonValue},
+        stream::read_line_limited,
+    },
+};
+use std::{
+    cell::Ref,
+    collections::HashMap,
+    error::Error,
+    io::{BufRead, Read},
+    net::TcpStream,
+};
+
+// Room in the request line for the method, HTTP version and separators, on top of the target.
+const REQUEST_LINE_OVERHEAD: usize = 64;
+
+// Maximum number of empty lines skipped before the request line.
+const MAX_LEADING_EMPTY_LINES: usize = 8;
+
+// How strictly requests are parsed. Strict mode rejects messages that servers and proxies in front of this one could frame differently, which could be used to smuggle a request past them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    #[default]
+    Strict,
+    // Accepts bare LF line endings, obs-fold line continuations, whitespace before header colons and requests with both Content-Length and Transfer-Encoding. Transfer-Encoding is then used and the connection is closed after the response.
+    Lenient,
+}
 
 // Additional data about the request used only server-side.
 #[derive(Debug)]
@@ -12,69 +51,538 @@ pub struct StaticRequestData {
 #[derive(Debug)]
 pub struct Request {
     pub method: Method,
+    // Path of the request target, without the query.
     pub path: String,
+    // Query of the request target, without the leading "?".
+    pub query: Option<String>,
+    pub version: Version,
+    // Host the request is for, from the Host header or an absolute-form target.
+    pub host: Option<String>,
     pub headers: HashMap<String, String>,
+    pub cookies: CookieJar,
+    pub body: Body,
+    pub session: Option<Session>,
     pub static_request_data: Option<StaticRequestData>,
 }
 
 impl Request {
+    // Read a single request from a new connection. For persistent connections use read_request with a reader kept for the whole connection, as bytes read ahead are lost otherwise.
     pub fn build_request(stream: &TcpStream) -> Result<Request, Box<dyn Error>> {
-        let lines = read_stream_lines(stream);
-        match lines {
-            Ok(lines) => Ok(Self::get_request_struct(lines)),
-            Err(e) => return Err(e),
+        let reader = Body::shared_reader(stream.try_clone()?);
+        Ok(Self::read_request(&reader)?)
+    }
+
+    // Read the head of a request from the reader with the default limits in strict mode. The body is left in the reader until it is used.
+    pub fn read_request(reader: &SharedReader) -> Result<Request, ProtocolError> {
+        Self::read_request_with(reader, &RequestLimits::default(), ParseMode::Strict)
+    }
+
+    // Read the head of a request from the reader with custom limits and parse mode.
+    pub fn read_request_with(
+        reader: &SharedReader,
+        limits: &RequestLimits,
+        mode: ParseMode,
+    ) -> Result<Request, ProtocolError> {
+        let (request_line, header_lines) =
+            Self::read_head(&mut *reader.borrow_mut(), limits, mode)?;
+        let mut request = Self::get_request_struct(&request_line, header_lines, limits, mode)?;
+        Self::check_framing(&mut request, mode)?;
+        request.body = Self::get_body(&request, reader, limits, mode)?;
+        if let Some(expectation) = request.header("Expect") {
+            if !expectation.eq_ignore_ascii_case("100-continue") {
+                return Err(ProtocolError::ExpectationFailed(expectation.to_string()));
+            }
+        }
+        Ok(request)
+    }
+
+    // Whether the client waits for 100 Continue before sending the body. HTTP/1.0 clients can't ask for it.
+    pub fn expects_continue(&self) -> bool {
+        self.version == Version::Http11
+            && self
+                .header("Expect")
+                .is_some_and(|expectation| expectation.eq_ignore_ascii_case("100-continue"))
+    }
+
+    // Read the request line and header lines, up to the empty line ending the head.
+    fn read_head(
+        reader: &mut dyn BufRead,
+        limits: &RequestLimits,
+        mode: ParseMode,
+    ) -> Result<(String, Vec<String>), ProtocolError> {
+        let to_string = |line: Vec<u8>| {
+            String::from_utf8(line)
+                .map_err(|_| ProtocolError::BadRequest("Request head is not valid UTF-8".into()))
+        };
+        let strip_cr = |mut line: Vec<u8>| {
+            if line.ends_with(b"\r") {
+                line.pop();
+            } else if mode == ParseMode::Strict {
+                return Err(ProtocolError::BadRequest("Bare LF line ending".into()));
+            }
+            Ok(line)
+        };
+
+        // Empty lines before the request line are ignored, as some clients send an extra CRLF after a body.
+        let mut empty_lines = 0;
+        let request_line = loop {
+            let line = read_line_limited(reader, limits.max_target_length + REQUEST_LINE_OVERHEAD)?
+                .ok_or(ProtocolError::UriTooLong(limits.max_target_length))?;
+            let line = strip_cr(line)?;
+            if !line.is_empty() {
+                break to_string(line)?;
+            }
+            empty_lines += 1;
+            if empty_lines > MAX_LEADING_EMPTY_LINES {
+                return Err(ProtocolError::BadRequest("Missing request line".into()));
+            }
+        };
+
+        let mut header_lines = Vec::new();
+        let mut header_size = 0;
+        loop {
+            let line = read_line_limited(reader, limits.max_header_size - header_size)?
+                .ok_or(ProtocolError::HeadersTooLarge)?;
+            let line = strip_cr(line)?;
+            if line.is_empty() {
+                break;
+            }
+            header_size += line.len();
+            if header_lines.len() == limits.max_headers {
+                return Err(ProtocolError::HeadersTooLarge);
+            }
+            header_lines.push(to_string(line)?);
+        }
+        Ok((request_line, header_lines))
+    }
+
+    // Reject framing headers that could be read differently by other servers on the way.
+    fn check_framing(request: &mut Request, mode: ParseMode) -> Result<(), ProtocolError> {
+        let bad_request = |message: &str| Err(ProtocolError::BadRequest(message.into()));
+        if let Some(length) = request.header("Content-Length") {
+            // Repeated fields were combined into a list, which is only allowed if all values are the same.
+            let mut values = length.split(',').map(str::trim);
+            let first = values.next().unwrap_or_default().to_string();
+            if first.is_empty()
+                || !first.bytes().all(|byte| byte.is_ascii_digit())
+                || values.any(|value| value != first)
+            {
+                return bad_request("Invalid Content-Length header");
+            }
+            request.replace_header("Content-Length", &first);
+        }
+
+        let Some(encoding) = request.header("Transfer-Encoding") else {
+            return Ok(());
+        };
+        if mode == ParseMode::Strict {
+            let chunked = encoding
+                .split(',')
+                .filter(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+                .count();
+            if chunked > 1 {
+                return bad_request("Transfer-Encoding applies chunked more than once");
+            }
+            if request.version == Version::Http10 {
+                return bad_request("Transfer-Encoding in a HTTP/1.0 request");
+            }
+        }
+        if request.header("Content-Length").is_some() {
+            if mode == ParseMode::Strict {
+                return bad_request("Both Content-Length and Transfer-Encoding");
+            }
+            // The connection can't be trusted for another request after ambiguous framing.
+            request
+                .headers
+                .retain(|name, _| !name.eq_ignore_ascii_case("Content-Length"));
+            request.replace_header("Connection", "close");
+        }
+        Ok(())
+    }
+
+    fn replace_header(&mut self, name: &str, value: &str) {
+        self.headers
+            .retain(|key, _| !key.eq_ignore_ascii_case(name));
+        self.headers.insert(name.to_string(), value.to_string());
+    }
+
+    fn get_body(
+        request: &Request,
+        reader: &SharedReader,
+        limits: &RequestLimits,
+        mode: ParseMode,
+    ) -> Result<Body, ProtocolError> {
+        let strict = mode == ParseMode::Strict;
+        // Transfer-Encoding takes precedence over Content-Length.
+        if let Some(encoding) = request.header("Transfer-Encoding") {
+            let last = encoding.rsplit(',').next().unwrap_or_default().trim();
+            if !last.eq_ignore_ascii_case("chunked") {
+                return Err(ProtocolError::NotImplemented(format!(
+                    "Transfer-Encoding {}",
+                    encoding
+                )));
+            }
+            return Ok(Body::new(
+                reader.clone(),
+                Framing::Chunked(0),
+                limits.max_body_size,
+                strict,
+            ));
+        }
+        match request.header("Content-Length") {
+            Some(length) => match length.trim().parse::<usize>() {
+                Ok(0) => Ok(Body::empty()),
+                Ok(length) if length > limits.max_body_size => {
+                    Err(ProtocolError::ContentTooLarge(limits.max_body_size))
+                }
+                Ok(length) => Ok(Body::new(
+                    reader.clone(),
+                    Framing::Length(length),
+                    limits.max_body_size,
+                    strict,
+                )),
+                Err(_) => Err(ProtocolError::BadRequest(
+                    "Invalid Content-Length header".into(),
+                )),
+            },
+            None => Ok(Body::empty()),
         }
     }
 
-    fn get_request_struct(lines: Vec<String>) -> Request {
+    // Whether the client wants to keep the connection open after the response. HTTP/1.1 connections are persistent unless closed, HTTP/1.0 connections only when asked for.
+    pub fn wants_keep_alive(&self) -> bool {
+        let connection = self.header("Connection").unwrap_or_default();
+        let has_option = |option: &str| {
+            connection
+                .split(',')
+                .any(|value| value.trim().eq_ignore_ascii_case(option))
+        };
+        match self.version {
+            Version::Http11 => !has_option("close"),
+            Version::Http10 => has_option("keep-alive"),
+        }
+    }
+
+    fn get_request_struct(
+        request_line: &str,
+        header_lines: Vec<String>,
+        limits: &RequestLimits,
+        mode: ParseMode,
+    ) -> Result<Request, ProtocolError> {
+        let (method, target, version) = parse_request_line(request_line, limits)?;
+        let (path, query, authority) = match target {
+            RequestTarget::Origin { path, query } => (path, query, None),
+            RequestTarget::Absolute {
+                authority,
+                path,
+                query,
+            } => (path, query, Some(authority)),
+            // Rejected by parse_request_line.
+            RequestTarget::Authority(_) | RequestTarget::Asterisk => unreachable!(),
+        };
         let mut request = Request {
-            method: Method::default(),
-            path: String::new(),
+            method,
+            path,
+            query,
+            version,
+            host: None,
             headers: HashMap::new(),
+            cookies: CookieJar::new(),
+            body: Body::empty(),
+            session: None,
             static_request_data: None,
         };
 
-        let mut headers: Vec<String> = Vec::new();
-        for line in lines {
-            if Self::starts_with_http_request_type(&line) {
-                let (method, path) = Self::get_request_type_info(&line);
-                request.method = match Method::from_str(&method) {
-                    Ok(method) => method,
-                    Err(e) => {
-                        log!("Stream Error: {:#?}", e);
-                        return request;
-                    }
-                };
-                request.path = path;
-            } else {
-                headers.push(line);
+        let mut host_count = 0;
+        for line in &Self::unfold_header_lines(header_lines, mode)? {
+            let (name, value) = parse_header_line(line)?;
+            if name.eq_ignore_ascii_case("Host") {
+                host_count += 1;
             }
-            for header in &headers {
-                let split = header.split(": ").collect::<Vec<&str>>();
-                if let [name, value] = split.as_slice() {
+            // Repeated fields are combined into one value, in order.
+            let existing = request
+                .headers
+                .iter_mut()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name));
+            match existing {
+                Some((_, existing)) => {
+                    let separator = if name.eq_ignore_ascii_case("Cookie") {
+                        "; "
+                    } else {
+                        ", "
+                    };
+                    existing.push_str(separator);
+                    existing.push_str(value);
+                }
+                None => {
                     request.headers.insert(name.to_string(), value.to_string());
                 }
             }
         }
 
-        request
+        // HTTP/1.1 requests must have exactly one valid Host header. An absolute-form target overrides it.
+        let host = request.header("Host").map(str::to_string);
+        if host_count > 1 || host.as_deref().is_some_and(|host| !is_valid_host(host)) {
+            return Err(ProtocolError::BadRequest("Invalid Host header".into()));
+        }
+        if host.is_none() && version == Version::Http11 {
+            return Err(ProtocolError::BadRequest("Missing Host header".into()));
+        }
+        request.host = authority.or(host);
+
+        if let Some(cookie_header) = request.header("Cookie") {
+            request.cookies = CookieJar::parse(cookie_header);
+        }
+        Ok(request)
     }
 
-    fn starts_with_http_request_type(line: &str) -> bool {
-        for http_request_type in Method::get_str_vec() {
-            if line.starts_with(http_request_type) {
-                return true;
+    // Handle obs-fold continuation lines, which start with whitespace. Lenient mode joins them to the previous line with a space and removes whitespace before colons.
+    fn unfold_header_lines(
+        header_lines: Vec<String>,
+        mode: ParseMode,
+    ) -> Result<Vec<String>, ProtocolError> {
+        if mode == ParseMode::Strict {
+            if header_lines
+                .iter()
+                .any(|line| line.starts_with([' ', '\t']))
+            {
+                return Err(ProtocolError::BadRequest("Obsolete line folding".into()));
+            }
+            return Ok(header_lines);
+        }
+        let mut lines: Vec<String> = Vec::with_capacity(header_lines.len());
+        for line in header_lines {
+            if line.starts_with([' ', '\t']) {
+                let Some(last) = lines.last_mut() else {
+                    return Err(ProtocolError::BadRequest("Obsolete line folding".into()));
+                };
+                last.push(' ');
+                last.push_str(line.trim_matches([' ', '\t']));
+                continue;
+            }
+            match line.split_once(':') {
+                Some((name, value)) => {
+                    lines.push(format!("{}:{}", name.trim_end_matches([' ', '\t']), value))
+                }
+                None => lines.push(line),
             }
         }
-        false
+        Ok(lines)
+    }
+
+    // Get the session of the request. Only available on routers with sessions enabled.
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    // Get the whole request body, reading it from the connection on first use.
+    pub fn body(&self) -> Result<Ref<'_, [u8]>, BodyError> {
+        self.body.bytes()
+    }
+
+    // Decode an application/x-www-form-urlencoded body. Rejects other content types with 415 Unsupported Media Type.
+    pub fn form(&self) -> Result<FormData, Rejection> {
+        require_content_type(self, FORM_CONTENT_TYPE)?;
+        let body = self.body()?;
+        std::str::from_utf8(&body)
+            .ok()
+            .and_then(FormData::parse)
+            .ok_or_else(|| Rejection::bad_request("Form data is not valid UTF-8"))
     }
 
-    // Get the request type and path from the first line of the request.
-    fn get_request_type_info(line: &String) -> (String, String) {
-        let mut iter = line.split_whitespace();
-        (
-            iter.next().unwrap_or_default().to_string(),
-            iter.next().unwrap_or_default().to_string(),
+    // Parse a JSON body into any type implementing FromJson (JsonValue for untyped access). Rejects other content types with 415 Unsupported Media Type and invalid JSON with 400 Bad Request.
+    pub fn json<T: FromJson>(&self) -> Result<T, Rejection> {
+        require_content_type(self, JSON_CONTENT_TYPE)?;
+        let body = self.body()?;
+        let body = std::str::from_utf8(&body)
+            .map_err(|_| Rejection::bad_request("JSON body is not valid UTF-8"))?;
+        let value = JsonValue::parse(body)
+            .map_err(|e| Rejection::bad_request(&format!("Invalid JSON: {}", e)))?;
+        T::from_json(&value).map_err(|e| Rejection::bad_request(&format!("Unexpected JSON: {}", e)))
+    }
+
+    // Parse a multipart/form-data body with the default limits. Parts are streamed from the connection one at a time.
+    pub fn multipart(&self) -> Result<Multipart<Box<dyn Read>>, Rejection> {
+        self.multipart_with(MultipartConfig::default())
+    }
+
+    // Parse a multipart/form-data body with custom limits.
+    pub fn multipart_with(
+        &self,
+        config: MultipartConfig,
+    ) -> Result<Multipart<Box<dyn Read>>, Rejection> {
+        require_content_type(self, MULTIPART_CONTENT_TYPE)?;
+        let boundary = self
+            .header("Content-Type")
+            .and_then(get_boundary)
+            .ok_or_else(|| Rejection::bad_request("Missing multipart boundary"))?;
+        Ok(Multipart::new(self.body.reader()?, &boundary, config))
+    }
+
+    // Get a header value by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn parse(raw: &str, mode: ParseMode) -> Result<Request, ProtocolError> {
+        let reader = Body::shared_reader(Cursor::new(raw.as_bytes().to_vec()));
+        Request::read_request_with(&reader, &RequestLimits::default(), mode)
+    }
+
+    fn status(raw: &str, mode: ParseMode) -> Option<usize> {
+        parse(raw, mode)
+            .err()
+            .and_then(|e| e.status())
+            .map(|(code, _)| code)
+    }
+
+    // Known request smuggling payloads, each rejected with 400 Bad Request in strict mode.
+    const SMUGGLING_CORPUS: &[&str] = &[
+        // CL.TE and TE.CL: both framing headers.
+        "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 6\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nG",
+        "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nContent-Length: 4\r\n\r\n5c\r\nGPOST / HTTP/1.1\r\n\r\n0\r\n\r\n",
+        // Differing duplicate Content-Length.
+        "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nhello!",
+        "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5, 6\r\n\r\nhello!",
+        // Content-Length values other parsers read differently.
+        "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: +5\r\n\r\nhello",
+        "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 0x5\r\n\r\nhello",
+        "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5 5\r\n\r\nhello",
+        // Whitespace before the colon hides the header from some parsers.
+        "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding : chunked\r\n\r\n0\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: a\r\nContent-Length\t: 5\r\n\r\nhello",
+        // obs-fold continuation of a framing header.
+        "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding:\r\n chunked\r\n\r\n0\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: a\r\nX: y\r\n\tTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        // Bare LF line endings.
+        "GET / HTTP/1.1\nHost: a\n\n",
+        "GET / HTTP/1.1\r\nHost: a\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        // chunked applied twice, and chunked in HTTP/1.0.
+        "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked, chunked\r\n\r\n0\r\n\r\n",
+        "POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        // Malformed request lines and header names.
+        "GET  / HTTP/1.1\r\nHost: a\r\n\r\n",
+        "GET / HTTP/1.1 \r\nHost: a\r\n\r\n",
+        "GET /\tHTTP/1.1\r\nHost: a\r\n\r\n",
+        "GET / HTTP/1.1\r\nHost: a\r\nX\rY: z\r\n\r\n",
+        "GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n",
+        "GET / HTTP/1.1\r\n\r\n",
+    ];
+
+    #[test]
+    fn strict_mode_rejects_smuggling_corpus() {
+        for raw in SMUGGLING_CORPUS {
+            assert_eq!(status(raw, ParseMode::Strict), Some(400), "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn strict_mode_is_the_default() {
+        assert_eq!(ParseMode::default(), ParseMode::Strict);
+        let reader = Body::shared_reader(Cursor::new(SMUGGLING_CORPUS[0].as_bytes().to_vec()));
+        assert!(Request::read_request(&reader).is_err());
+    }
+
+    #[test]
+    fn unknown_transfer_coding_is_not_implemented() {
+        let raw = "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: x\r\n\r\n";
+        assert_eq!(status(raw, ParseMode::Strict), Some(501));
+        assert_eq!(status(raw, ParseMode::Lenient), Some(501));
+    }
+
+    #[test]
+    fn equal_duplicate_content_lengths_are_accepted() {
+        let raw =
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nhello";
+        let request = parse(raw, ParseMode::Strict).unwrap();
+        assert_eq!(request.header("Content-Length"), Some("5"));
+        assert_eq!(&*request.body().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn chunked_body_with_bare_lf_is_rejected_in_strict_mode() {
+        let raw = "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5\nhello\n0\n\n";
+        let request = parse(raw, ParseMode::Strict).unwrap();
+        assert!(request.body().is_err());
+        let request = parse(raw, ParseMode::Lenient).unwrap();
+        assert_eq!(&*request.body().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn lenient_mode_prefers_transfer_encoding_and_closes() {
+        let raw = "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 100\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        let request = parse(raw, ParseMode::Lenient).unwrap();
+        assert_eq!(request.header("Content-Length"), None);
+        assert!(!request.wants_keep_alive());
+        assert_eq!(&*request.body().unwrap(), b"abc");
+    }
+
+    #[test]
+    fn lenient_mode_accepts_folding_bare_lf_and_whitespace_before_colon() {
+        let raw = "GET / HTTP/1.1\nHost: a\nX-Long: one\n  two\nX-Space : b\n\n";
+        let request = parse(raw, ParseMode::Lenient).unwrap();
+        assert_eq!(request.header("X-Long"), Some("one two"));
+        assert_eq!(request.header("X-Space"), Some("b"));
+    }
+
+    #[test]
+    fn valid_requests_are_accepted_in_strict_mode() {
+        let request = parse(
+            "GET /a/b?c=d HTTP/1.1\r\nHost: a\r\nAccept: */*\r\nAccept: text/html\r\n\r\n",
+            ParseMode::Strict,
+        )
+        .unwrap();
+        assert_eq!(request.path, "/a/b");
+        assert_eq!(request.query.as_deref(), Some("c=d"));
+        assert_eq!(request.header("accept"), Some("*/*, text/html"));
+        let request = parse(
+            "GET http://b:80/x HTTP/1.1\r\nHost: a\r\n\r\n",
+            ParseMode::Strict,
         )
+        .unwrap();
+        assert_eq!(request.host.as_deref(), Some("b:80"));
+        assert_eq!(request.path, "/x");
+    }
+
+    // A chunked request carrying size bytes in chunks of 1000, read with a body limit of 100.
+    fn chunked_over_limit(size: usize) -> Request {
+        let mut raw =
+            String::from("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n");
+        for _ in 0..size / 1000 {
+            raw.push_str(&format!("3e8\r\n{}\r\n", "x".repeat(1000)));
+        }
+        raw.push_str("0\r\n\r\n");
+        let reader = Body::shared_reader(Cursor::new(raw.into_bytes()));
+        let limits = RequestLimits {
+            max_body_size: 100,
+            ..RequestLimits::default()
+        };
+        Request::read_request_with(&reader, &limits, ParseMode::Strict).unwrap()
+    }
+
+    #[test]
+    fn streamed_chunked_body_over_the_limit_fails() {
+        let request = chunked_over_limit(5000);
+        let mut body = Vec::new();
+        let e = request
+            .body
+            .reader()
+            .unwrap()
+            .read_to_end(&mut body)
+            .unwrap_err();
+        assert!(matches!(BodyError::from(e), BodyError::TooLarge(100)));
+        assert!(body.len() <= 100);
+
+        let request = chunked_over_limit(5000);
+        assert!(matches!(request.body(), Err(BodyError::TooLarge(100))));
     }
 }
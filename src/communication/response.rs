@@ -1,5 +1,10 @@
+use crate::communication::cookie::{Cookie, CookieError};
+use crate::communication::json::JSON_CONTENT_TYPE;
+use crate::communication::protocol::is_token;
+use crate::communication::secure_cookie::CookieKeys;
 use crate::utils::guess::guess_mime_type;
-use std::{io::Error, io::Write, net::TcpStream};
+use crate::utils::json::ToJson;
+use std::{io::Error, io::Write};
 
 // Representation of a HTTP response.
 #[derive(Debug)]
@@ -8,6 +13,13 @@ pub struct Response {
     pub status_message: String,
     pub content_type: Option<String>,
     pub content: Option<String>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Response {
@@ -17,16 +29,17 @@ impl Response {
             status_message: String::from("OK"),
             content_type: None,
             content: None,
+            headers: Vec::new(),
         }
     }
 
-    // Send this response back to the client.
-    pub fn send(&mut self, stream: &TcpStream) -> Result<(), Error> {
+    // Send this response back to the client. Pass a &TcpStream, or a buffered writer to batch several responses.
+    pub fn send<W: Write>(&mut self, mut stream: W) -> Result<(), Error> {
         match (&self.content_type, &self.content) {
             // If the content type is set, but the content is not, send the content type.
             (Some(content_type), Some(_)) => {
-                self.send_with_content(&stream, content_type)?;
-                return Ok(());
+                self.send_with_content(&mut stream, content_type)?;
+                Ok(())
             }
             // If the content type is not set, but the content is, guess the content type and send it.
             (None, Some(content)) => {
@@ -34,40 +47,56 @@ impl Response {
                     Some(content_type) => content_type.clone(),
                     None => guess_mime_type(content.as_str()),
                 };
-                self.send_with_content(&stream, &content_type)?;
-                return Ok(());
+                self.send_with_content(&mut stream, &content_type)?;
+                Ok(())
             }
             // If neither the content type nor the content is set, send no content.
             _ => {
-                self.send_without_content(&stream)?;
-                return Ok(());
+                self.send_without_content(&mut stream)?;
+                Ok(())
             }
         }
     }
 
-    fn send_with_content(&self, mut stream: &TcpStream, content_type: &str) -> Result<(), Error> {
+    fn send_with_content(&self, stream: &mut impl Write, content_type: &str) -> Result<(), Error> {
         let status_code = &self.status_code;
         let status_message = &self.status_message;
         let content = &self.content.as_ref().unwrap();
         let content_length = content.len();
+        let headers = self.format_headers();
 
         let format = format!(
             "HTTP/1.1 {status_code} {status_message}\r\n\
              Content-Type: {content_type}\r\n\
              Content-Length: {content_length}\r\n\
+             {headers}\
              \r\n\
              {content}",
         );
         stream.write_all(format.as_bytes())
     }
 
-    fn send_without_content(&self, mut stream: &TcpStream) -> Result<(), Error> {
+    fn send_without_content(&self, stream: &mut impl Write) -> Result<(), Error> {
         let status_code = &self.status_code;
         let status_message = &self.status_message;
-        let format = format!("HTTP/1.1 {status_code} {status_message}\r\n\"",);
+        let headers = self.format_headers();
+        let format = format!(
+            "HTTP/1.1 {status_code} {status_message}\r\n\
+             Content-Length: 0\r\n\
+             {headers}\
+             \r\n"
+        );
         stream.write_all(format.as_bytes())
     }
 
+    // Format the additional headers, each terminated by CRLF.
+    fn format_headers(&self) -> String {
+        self.headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}\r\n", name, value))
+            .collect()
+    }
+
     pub fn set_status(&mut self, status_code: usize, status_message: &str) {
         self.status_code = status_code;
         self.status_message = status_message.to_string();
@@ -93,4 +122,135 @@ impl Response {
     pub fn set_content(&mut self, content: &str) {
         self.content = Some(content.to_string());
     }
+
+    // Set the content to the serialized JSON value, with the application/json content type.
+    pub fn json<T: ToJson + ?Sized>(&mut self, value: &T) {
+        self.set_contents(JSON_CONTENT_TYPE, &value.to_json().to_string());
+    }
+
+    // Set a header, replacing any existing headers with the same name.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.add_header(name, value);
+    }
+
+    // Add a header, keeping any existing headers with the same name (e.g. Set-Cookie). Panics on a name that is not a token or a value containing CR, LF or NUL, which would let the header split the response.
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        assert!(is_token(name), "Invalid header name {:?}", name);
+        assert!(
+            !value.contains(['\r', '\n', '\0']),
+            "Invalid value for header {}: must not contain CR, LF or NUL",
+            name
+        );
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Send a cookie to the client, each cookie is emitted as its own Set-Cookie header. Fails without adding anything if the cookie could inject attributes or headers.
+    pub fn add_cookie(&mut self, cookie: Cookie) -> Result<(), CookieError> {
+        self.add_header("Set-Cookie", &cookie.to_header_value()?);
+        Ok(())
+    }
+
+    // Send a cookie whose value is signed with the primary key, so it can be read but not modified by the client.
+    pub fn add_signed_cookie(
+        &mut self,
+        cookie: Cookie,
+        keys: &CookieKeys,
+    ) -> Result<(), CookieError> {
+        self.add_cookie(keys.sign(cookie))
+    }
+
+    // Send a cookie whose value is encrypted with the primary key, so it can be neither read nor modified by the client.
+    pub fn add_private_cookie(
+        &mut self,
+        cookie: Cookie,
+        keys: &CookieKeys,
+    ) -> Result<(), CookieError> {
+        self.add_cookie(keys.encrypt(cookie))
+    }
+
+    // Tell the client to delete a cookie. The path and domain must match the ones the cookie was set with.
+    pub fn remove_cookie(&mut self, cookie: Cookie) -> Result<(), CookieError> {
+        self.add_cookie(cookie.into_removal())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(response: &mut Response) -> String {
+        let mut buffer = Vec::new();
+        response.send(&mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn set_header_replaces_and_add_header_appends() {
+        let mut response = Response::new();
+        response.set_header("X-Test", "1");
+        response.set_header("x-test", "2");
+        response.add_header("Set-Cookie", "a=1");
+        response.add_header("Set-Cookie", "b=2");
+        assert_eq!(
+            sent(&mut response),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nx-test: 2\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n\r\n"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid value for header X-Test")]
+    fn header_value_with_crlf_panics() {
+        Response::new().set_header("X-Test", "1\r\nX-Injected: 1");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid value for header X-Test")]
+    fn header_value_with_nul_panics() {
+        Response::new().add_header("X-Test", "1\0");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid header name")]
+    fn invalid_header_name_panics() {
+        Response::new().add_header("X-Test: 1\r\nX-Injected", "1");
+    }
+
+    #[test]
+    fn cookie_values_cannot_inject_headers() {
+        let mut response = Response::new();
+        response
+            .add_cookie(Cookie::new("id", "1\r\nX-Injected: 1"))
+            .unwrap();
+        let sent = sent(&mut response);
+        assert!(sent.contains("Set-Cookie: id=1%0D%0AX-Injected:%201\r\n"));
+        assert!(!sent.contains("\r\nX-Injected"));
+    }
+
+    #[test]
+    fn invalid_cookies_are_not_added() {
+        let mut response = Response::new();
+        assert!(response.add_cookie(Cookie::new("a b", "1")).is_err());
+        assert_eq!(response.header("Set-Cookie"), None);
+    }
+
+    #[test]
+    fn removed_cookies_expire_immediately() {
+        let mut response = Response::new();
+        response
+            .remove_cookie(Cookie::build("id", "abc").path("/").finish())
+            .unwrap();
+        assert_eq!(
+            response.header("Set-Cookie"),
+            Some("id=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT")
+        );
+    }
 }
@@ -1,22 +1,24 @@
 use std::{collections::HashMap, sync::Arc};
 
+use crate::utils::thread_pool::Priority;
+
 use super::{method::Method, request::Request, response::Response};
 
 // User defined function type found at every defined route (path)
-pub type RouteFunc = Arc<dyn Fn(&Request, &mut Response) -> () + Send + Sync + 'static>;
+pub type RouteFunc = Arc<dyn Fn(&Request, &mut Response) + Send + Sync + 'static>;
 
 #[derive(Clone)]
 pub struct Route {
     pub method_map: HashMap<Method, RouteFunc>,
+    // Priority lanes of methods that don't use the one of their router.
+    pub priorities: HashMap<Method, Priority>,
 }
 
 impl Route {
     pub fn new(method_map: Option<HashMap<Method, RouteFunc>>) -> Self {
-        match method_map {
-            Some(m) => Self { method_map: m },
-            None => Self {
-                method_map: HashMap::new(),
-            },
+        Self {
+            method_map: method_map.unwrap_or_default(),
+            priorities: HashMap::new(),
         }
     }
 }
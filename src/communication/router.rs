@@ -3,21 +3,25 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use crate::{ds::trie::Trie, log};
+use crate::{ds::trie::Trie, error, utils::thread_pool::Priority};
 
 use super::{
+    extract::FromRequest,
     method::Method,
-    middleware::Middleware,
+    middleware::{AfterMiddleware, Middleware},
     request::Request,
     response::Response,
     route::{Route, RouteFunc},
+    session::SessionManager,
 };
 
 #[derive(Clone)]
 pub struct Router {
     pub base_path: String,
     middleware: Vec<Middleware>,
+    after_middleware: Vec<AfterMiddleware>,
     routes: Arc<Mutex<Trie<Route>>>,
+    priority: Priority,
 }
 
 impl Router {
@@ -25,26 +29,107 @@ impl Router {
         Self {
             base_path: String::from(base_path),
             middleware: Vec::new(),
+            after_middleware: Vec::new(),
             routes: Arc::new(Mutex::new(Trie::new())),
+            priority: Priority::Normal,
         }
     }
 
     // Create a route for the router.
     pub fn route<F>(&mut self, path: &str, method: &str, func: F)
     where
-        F: Fn(&Request, &mut Response) -> () + Send + Sync + 'static,
+        F: Fn(&Request, &mut Response) + Send + Sync + 'static,
     {
         self.create_route(path, method, func);
     }
 
+    // Create a route whose function receives data extracted from the request (e.g. Form<T>). If extraction fails, the function is not called and the client gets the rejection instead.
+    pub fn route_with<E, F>(&mut self, path: &str, method: &str, func: F)
+    where
+        E: FromRequest,
+        F: Fn(&Request, &mut Response, E) + Send + Sync + 'static,
+    {
+        self.create_route(
+            path,
+            method,
+            move |request, response| match E::from_request(request) {
+                Ok(extracted) => func(request, response, extracted),
+                Err(rejection) => rejection.apply(response),
+            },
+        );
+    }
+
+    // Set the priority lane of connections whose first request is for this router. The lane is picked once per connection, so later requests on a keep-alive connection stay in the lane of the first one, whatever their route. Clients that mix critical and other requests should use separate connections for them.
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+    }
+
+    // Set the priority lane of an existing route, overriding the one of the router. As with set_priority, it applies to connections whose first request is for the route.
+    pub fn route_priority(&mut self, path: &str, method: &str, priority: Priority) {
+        let Ok(method) = Method::from_str(method) else {
+            error!("Method Error: {}", method);
+            return;
+        };
+        let mut routes = self.routes.lock().unwrap();
+        let path = format!("{}{}", self.base_path, path);
+        match routes.search(path.as_str()) {
+            Some(mut route) if route.method_map.contains_key(&method) => {
+                route.priorities.insert(method, priority);
+                routes.insert(path.as_str(), route);
+            }
+            _ => error!(
+                "Route Priority Error: no {} route for path: {}",
+                method.as_str(),
+                path
+            ),
+        }
+    }
+
+    // Priority lane of a request for the router.
+    pub fn priority_for(&self, path: &str, method: &Method) -> Priority {
+        self.routes
+            .lock()
+            .unwrap()
+            .search(path)
+            .and_then(|route| route.priorities.get(method).copied())
+            .unwrap_or(self.priority)
+    }
+
+    // Whether the router or one of its routes uses a lane other than the normal one.
+    pub fn has_priorities(&self) -> bool {
+        self.priority != Priority::Normal
+            || self.routes.lock().unwrap().values().iter().any(|route| {
+                route
+                    .priorities
+                    .values()
+                    .any(|&priority| priority != Priority::Normal)
+            })
+    }
+
     // Register a middleware with the router.
     pub fn middleware<F>(&mut self, func: F)
     where
-        F: Fn(Option<&Middleware>, &mut Request) -> () + Send + Sync + 'static,
+        F: Fn(Option<&Middleware>, &mut Request) + Send + Sync + 'static,
     {
         self.middleware.push(Middleware::new(func));
     }
 
+    // Register a middleware executed after the route, which can modify the response.
+    pub fn after_middleware<F>(&mut self, func: F)
+    where
+        F: Fn(&Request, &mut Response) + Send + Sync + 'static,
+    {
+        self.after_middleware.push(AfterMiddleware::new(func));
+    }
+
+    // Enable sessions for every route of the router.
+    pub fn sessions(&mut self, manager: &SessionManager) {
+        let loader = manager.clone();
+        self.middleware(move |_mid, request| loader.load(request));
+        let committer = manager.clone();
+        self.after_middleware(move |request, response| committer.commit(request, response));
+    }
+
     // Find a route for the router given a request. Returns None if no route is found.
     pub fn find_route(&self, request: &mut Request) -> Option<Route> {
         self.routes.lock().unwrap().search(&request.path)
@@ -52,7 +137,7 @@ impl Router {
 
     pub fn execute_middleware(&self, request: &mut Request) {
         let mut prev_mid: Option<&Middleware> = None;
-        if self.middleware.len() > 0 {
+        if !self.middleware.is_empty() {
             for mid in &self.middleware {
                 (mid.func)(prev_mid, request);
                 prev_mid = Some(mid);
@@ -60,25 +145,32 @@ impl Router {
         }
     }
 
+    pub fn execute_after_middleware(&self, request: &Request, response: &mut Response) {
+        for mid in &self.after_middleware {
+            (mid.func)(request, response);
+        }
+    }
+
     // Register a route with the router.
     fn create_route<F>(&mut self, path: &str, method: &str, func: F)
     where
-        F: Fn(&Request, &mut Response) -> () + Send + Sync + 'static,
+        F: Fn(&Request, &mut Response) + Send + Sync + 'static,
     {
         match Method::from_str(method) {
             Ok(method) => {
                 let mut routes = self.routes.lock().unwrap();
                 let path = format!("{}{}", self.base_path, path);
                 match routes.search(path.as_str()) {
-                    Some(route) => {
-                        let mut method_map = route.method_map;
+                    Some(mut route) => {
                         assert!(
-                            !method_map.contains_key(&method),
+                            !route.method_map.contains_key(&method),
                             "Route already exists for path: {} and method: {:#?}",
                             path,
                             method
                         );
-                        Self::insert_route(&mut method_map, method, func);
+                        Self::insert_route(&mut route.method_map, method, func);
+                        // The trie returns a copy of the route, so store the updated one back.
+                        routes.insert(path.as_str(), route);
                     }
                     None => {
                         let mut method_map = HashMap::new();
@@ -87,13 +179,13 @@ impl Router {
                     }
                 }
             }
-            Err(e) => log!("Method Error: {:#?}", e),
+            Err(e) => error!("Method Error: {:#?}", e),
         }
     }
 
     fn insert_route<F>(method_map: &mut HashMap<Method, RouteFunc>, method: Method, func: F)
     where
-        F: Fn(&Request, &mut Response) -> () + Send + Sync + 'static,
+        F: Fn(&Request, &mut Response) + Send + Sync + 'static,
     {
         method_map.insert(method, Arc::new(func));
     }
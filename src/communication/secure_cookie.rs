@@ -0,0 +1,252 @@
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+
+use super::cookie::Cookie;
+use crate::utils::base64::{decode_url_safe, encode_url_safe};
+use crate::utils::crypto::{constant_time_eq, hmac_sha256, random_bytes, DIGEST_SIZE};
+
+const MIN_KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 16;
+
+// Error returned when a key is created from too little key material.
+#[derive(Debug)]
+pub struct KeyError {
+    len: usize,
+}
+
+impl Display for KeyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Cookie key must be at least {} bytes, got {}",
+            MIN_KEY_LEN, self.len
+        )
+    }
+}
+
+impl Error for KeyError {}
+
+// Secret key used to sign and encrypt cookies. Separate keys for signing, encryption and authenticating encrypted cookies are derived from the master key.
+#[derive(Clone)]
+pub struct Key {
+    signing: [u8; DIGEST_SIZE],
+    encryption: [u8; DIGEST_SIZE],
+    authentication: [u8; DIGEST_SIZE],
+}
+
+impl Debug for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Key {{ .. }}")
+    }
+}
+
+impl Key {
+    pub fn from_bytes(master: &[u8]) -> Result<Key, KeyError> {
+        if master.len() < MIN_KEY_LEN {
+            return Err(KeyError { len: master.len() });
+        }
+        Ok(Key {
+            signing: hmac_sha256(master, b"tiny-http-server cookie signing"),
+            encryption: hmac_sha256(master, b"tiny-http-server cookie encryption"),
+            authentication: hmac_sha256(master, b"tiny-http-server cookie encryption mac"),
+        })
+    }
+
+    // Generate a new random key. Cookies protected by it become invalid once the process exits unless the key is persisted.
+    pub fn generate() -> Key {
+        Self::from_bytes(&random_bytes(64)).unwrap()
+    }
+
+    fn sign(&self, name: &str, value: &[u8]) -> [u8; DIGEST_SIZE] {
+        Self::mac(&self.signing, name, value)
+    }
+
+    // Tag of an encrypted cookie. It uses a key of its own, so a signature is never a valid tag, nor a tag a valid signature.
+    fn tag(&self, name: &str, data: &[u8]) -> [u8; DIGEST_SIZE] {
+        Self::mac(&self.authentication, name, data)
+    }
+
+    fn mac(key: &[u8], name: &str, value: &[u8]) -> [u8; DIGEST_SIZE] {
+        let mut message = Vec::with_capacity(name.len() + 1 + value.len());
+        message.extend_from_slice(name.as_bytes());
+        message.push(b'=');
+        message.extend_from_slice(value);
+        hmac_sha256(key, &message)
+    }
+
+    // XOR the data with a keystream of HMAC-SHA256(key, nonce || counter) blocks.
+    fn apply_keystream(&self, nonce: &[u8], data: &mut [u8]) {
+        for (counter, chunk) in data.chunks_mut(DIGEST_SIZE).enumerate() {
+            let mut input = nonce.to_vec();
+            input.extend_from_slice(&(counter as u64).to_be_bytes());
+            let block = hmac_sha256(&self.encryption, &input);
+            for (byte, key) in chunk.iter_mut().zip(block) {
+                *byte ^= key;
+            }
+        }
+    }
+}
+
+// Set of keys used for signed and encrypted cookies. The first key protects new cookies, and all keys are tried when verifying, so keys can be rotated without logging everyone out.
+#[derive(Debug, Clone)]
+pub struct CookieKeys {
+    keys: Vec<Key>,
+}
+
+impl CookieKeys {
+    pub fn new(key: Key) -> Self {
+        Self { keys: vec![key] }
+    }
+
+    // Make a new key the primary key. The previous keys are still accepted for verification.
+    pub fn rotate(&mut self, key: Key) {
+        self.keys.insert(0, key);
+    }
+
+    // Keep only the given number of most recent keys, invalidating cookies protected by older keys.
+    pub fn retain_latest(&mut self, count: usize) {
+        self.keys.truncate(count.max(1));
+    }
+
+    // Sign the cookie value so tampering can be detected. The value stays readable by the client.
+    pub fn sign(&self, mut cookie: Cookie) -> Cookie {
+        let signature = self.keys[0].sign(&cookie.name, cookie.value.as_bytes());
+        cookie.value = format!("{}.{}", cookie.value, encode_url_safe(&signature));
+        cookie
+    }
+
+    // Verify a signed cookie value, returning the original value if any key produced the signature.
+    pub fn verify(&self, name: &str, value: &str) -> Option<String> {
+        let (value, signature) = value.rsplit_once('.')?;
+        let signature = decode_url_safe(signature)?;
+        self.keys
+            .iter()
+            .any(|key| constant_time_eq(&key.sign(name, value.as_bytes()), &signature))
+            .then(|| value.to_string())
+    }
+
+    // Encrypt and authenticate the cookie value so the client can neither read nor modify it.
+    pub fn encrypt(&self, mut cookie: Cookie) -> Cookie {
+        let key = &self.keys[0];
+        let mut payload = random_bytes(NONCE_LEN);
+        let mut ciphertext = cookie.value.clone().into_bytes();
+        key.apply_keystream(&payload, &mut ciphertext);
+        payload.extend_from_slice(&ciphertext);
+        let tag = key.tag(&cookie.name, &payload);
+        payload.extend_from_slice(&tag);
+        cookie.value = encode_url_safe(&payload);
+        cookie
+    }
+
+    // Decrypt an encrypted cookie value, returning None if it was not produced by any of the keys.
+    pub fn decrypt(&self, name: &str, value: &str) -> Option<String> {
+        let payload = decode_url_safe(value)?;
+        if payload.len() < NONCE_LEN + DIGEST_SIZE {
+            return None;
+        }
+        let (data, tag) = payload.split_at(payload.len() - DIGEST_SIZE);
+        let key = self
+            .keys
+            .iter()
+            .find(|key| constant_time_eq(&key.tag(name, data), tag))?;
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let mut plaintext = ciphertext.to_vec();
+        key.apply_keystream(nonce, &mut plaintext);
+        String::from_utf8(plaintext).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> CookieKeys {
+        CookieKeys::new(Key::from_bytes(&[7; 32]).unwrap())
+    }
+
+    #[test]
+    fn signed_cookie_round_trip() {
+        let keys = keys();
+        let cookie = keys.sign(Cookie::new("user", "alice"));
+        assert!(cookie.value.starts_with("alice."));
+        assert_eq!(keys.verify("user", &cookie.value).as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn tampered_signed_cookie_is_rejected() {
+        let keys = keys();
+        let cookie = keys.sign(Cookie::new("user", "alice"));
+        let tampered = cookie.value.replacen("alice", "mallory", 1);
+        assert_eq!(keys.verify("user", &tampered), None);
+        assert_eq!(keys.verify("admin", &cookie.value), None);
+        assert_eq!(keys.verify("user", "alice"), None);
+    }
+
+    #[test]
+    fn encrypted_cookie_round_trip() {
+        let keys = keys();
+        let cookie = keys.encrypt(Cookie::new("session", "secret value"));
+        assert!(!cookie.value.contains("secret"));
+        assert_eq!(
+            keys.decrypt("session", &cookie.value).as_deref(),
+            Some("secret value")
+        );
+
+        let mut tampered = decode_url_safe(&cookie.value).unwrap();
+        tampered[NONCE_LEN] ^= 1;
+        assert_eq!(keys.decrypt("session", &encode_url_safe(&tampered)), None);
+    }
+
+    #[test]
+    fn signatures_and_encryption_tags_differ() {
+        let keys = keys();
+        let cookie = keys.encrypt(Cookie::new("session", "value"));
+        let payload = decode_url_safe(&cookie.value).unwrap();
+        let (data, tag) = payload.split_at(payload.len() - DIGEST_SIZE);
+        assert!(!constant_time_eq(&keys.keys[0].sign("session", data), tag));
+
+        // A signature can't pass as the tag of an encrypted value either.
+        let mut forged = data.to_vec();
+        forged.extend_from_slice(&keys.keys[0].sign("session", data));
+        assert_eq!(keys.decrypt("session", &encode_url_safe(&forged)), None);
+    }
+
+    #[test]
+    fn signatures_have_one_encoding() {
+        let keys = keys();
+        let cookie = keys.sign(Cookie::new("user", "alice"));
+        let (value, signature) = cookie.value.rsplit_once('.').unwrap();
+        // The signature is 32 bytes, so its last character carries two unused bits.
+        let last = signature.chars().last().unwrap();
+        let alphabet = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+        let index = alphabet.find(last).unwrap();
+        let altered = alphabet.chars().nth(index | 1).unwrap();
+        let altered = format!("{}.{}{}", value, &signature[..signature.len() - 1], altered);
+        assert_eq!(keys.verify("user", &altered), None);
+    }
+
+    #[test]
+    fn rotated_keys_still_verify() {
+        let mut keys = keys();
+        let old_signed = keys.sign(Cookie::new("user", "alice"));
+        let old_encrypted = keys.encrypt(Cookie::new("user", "alice"));
+        keys.rotate(Key::from_bytes(&[9; 32]).unwrap());
+        assert_eq!(
+            keys.verify("user", &old_signed.value).as_deref(),
+            Some("alice")
+        );
+        assert_eq!(
+            keys.decrypt("user", &old_encrypted.value).as_deref(),
+            Some("alice")
+        );
+
+        keys.retain_latest(1);
+        assert_eq!(keys.verify("user", &old_signed.value), None);
+        assert_eq!(keys.decrypt("user", &old_encrypted.value), None);
+    }
+
+    #[test]
+    fn short_keys_are_rejected() {
+        assert!(Key::from_bytes(&[1; 16]).is_err());
+    }
+}
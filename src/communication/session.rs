@@ -0,0 +1,419 @@
+pub mod store;
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use self::store::SessionStore;
+use super::cookie::{Cookie, SameSite};
+use super::request::Request;
+use super::response::Response;
+use crate::error;
+use crate::utils::base64::encode_url_safe;
+use crate::utils::crypto::random_bytes;
+
+// Session data as persisted by a session store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionRecord {
+    pub data: HashMap<String, String>,
+    pub created: SystemTime,
+    pub last_access: SystemTime,
+}
+
+impl SessionRecord {
+    pub fn new() -> Self {
+        let now = SystemTime::now();
+        Self {
+            data: HashMap::new(),
+            created: now,
+            last_access: now,
+        }
+    }
+}
+
+impl Default for SessionRecord {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct SessionState {
+    id: String,
+    record: SessionRecord,
+    is_new: bool,
+    modified: bool,
+    destroyed: bool,
+    stale_ids: Vec<String>,
+}
+
+// Key-value session of a client, available to route functions through the request.
+#[derive(Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+impl Debug for Session {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("Session")
+            .field("is_new", &state.is_new)
+            .field("data", &state.record.data)
+            .finish()
+    }
+}
+
+impl Session {
+    fn new(id: String, record: SessionRecord, is_new: bool) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SessionState {
+                id,
+                record,
+                is_new,
+                modified: false,
+                destroyed: false,
+                stale_ids: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn id(&self) -> String {
+        self.state.lock().unwrap().id.clone()
+    }
+
+    pub fn is_new(&self) -> bool {
+        self.state.lock().unwrap().is_new
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state.lock().unwrap().record.data.get(key).cloned()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.state.lock().unwrap().record.data.contains_key(key)
+    }
+
+    pub fn insert(&self, key: &str, value: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.record.data.insert(key.to_string(), value.to_string());
+        state.modified = true;
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        state.modified = true;
+        state.record.data.remove(key)
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.record.data.clear();
+        state.modified = true;
+    }
+
+    // Give the session a new ID while keeping its data. Call this on login and other privilege changes to prevent session fixation.
+    pub fn regenerate(&self) {
+        let mut state = self.state.lock().unwrap();
+        let old_id = std::mem::replace(&mut state.id, generate_session_id());
+        if !state.is_new {
+            state.stale_ids.push(old_id);
+        }
+        state.record.created = SystemTime::now();
+        state.is_new = true;
+        state.modified = true;
+    }
+
+    // Delete the session from the store and the client once the response is sent.
+    pub fn destroy(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.record.data.clear();
+        state.destroyed = true;
+    }
+}
+
+// Configuration of the session cookie and session lifetime.
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    pub cookie_name: String,
+    pub cookie_path: String,
+    pub cookie_domain: Option<String>,
+    pub secure: bool,
+    pub same_site: SameSite,
+    // Session expires when it has not been used for this long.
+    pub idle_timeout: Option<Duration>,
+    // Session expires this long after it was created (or regenerated), regardless of activity.
+    pub absolute_timeout: Option<Duration>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            cookie_name: String::from("sid"),
+            cookie_path: String::from("/"),
+            cookie_domain: None,
+            secure: false,
+            same_site: SameSite::Lax,
+            idle_timeout: Some(Duration::from_secs(30 * 60)),
+            absolute_timeout: Some(Duration::from_secs(24 * 60 * 60)),
+        }
+    }
+}
+
+// Loads sessions for requests and saves them after the route has run. Register with Router::sessions.
+#[derive(Clone)]
+pub struct SessionManager {
+    store: Arc<dyn SessionStore>,
+    config: Arc<SessionConfig>,
+}
+
+impl SessionManager {
+    pub fn new(store: impl SessionStore + 'static) -> Self {
+        Self::with_config(store, SessionConfig::default())
+    }
+
+    pub fn with_config(store: impl SessionStore + 'static, config: SessionConfig) -> Self {
+        Self {
+            store: Arc::new(store),
+            config: Arc::new(config),
+        }
+    }
+
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    // Remove expired sessions from the store.
+    pub fn cleanup(&self) {
+        if let Err(e) = self.store.cleanup() {
+            error!("Session Cleanup Error: {:#?}", e);
+        }
+    }
+
+    // Attach the session identified by the request cookie, or a new empty session, to the request.
+    pub fn load(&self, request: &mut Request) {
+        let existing = request
+            .cookies
+            .get(&self.config.cookie_name)
+            .and_then(|id| self.load_record(id).map(|record| (id.to_string(), record)));
+        request.session = Some(match existing {
+            Some((id, record)) => Session::new(id, record, false),
+            None => Session::new(generate_session_id(), SessionRecord::new(), true),
+        });
+    }
+
+    fn load_record(&self, id: &str) -> Option<SessionRecord> {
+        let record = match self.store.load(id) {
+            Ok(record) => record?,
+            Err(e) => {
+                error!("Session Load Error: {:#?}", e);
+                return None;
+            }
+        };
+        if self.is_expired(&record) {
+            self.destroy_record(id);
+            return None;
+        }
+        Some(record)
+    }
+
+    fn is_expired(&self, record: &SessionRecord) -> bool {
+        let now = SystemTime::now();
+        let exceeds = |since: SystemTime, timeout: Option<Duration>| {
+            timeout.is_some_and(|timeout| now.duration_since(since).unwrap_or_default() > timeout)
+        };
+        exceeds(record.last_access, self.config.idle_timeout)
+            || exceeds(record.created, self.config.absolute_timeout)
+    }
+
+    fn destroy_record(&self, id: &str) {
+        if let Err(e) = self.store.destroy(id) {
+            error!("Session Destroy Error: {:#?}", e);
+        }
+    }
+
+    // Persist the session of the request and send the session cookie if needed.
+    pub fn commit(&self, request: &Request, response: &mut Response) {
+        let Some(session) = request.session() else {
+            return;
+        };
+        let mut state = session.state.lock().unwrap();
+        for stale_id in state.stale_ids.drain(..) {
+            self.destroy_record(&stale_id);
+        }
+
+        if state.destroyed {
+            if !state.is_new {
+                self.destroy_record(&state.id);
+            }
+            if request.cookies.contains(&self.config.cookie_name) {
+                if let Err(e) = response.remove_cookie(self.cookie("")) {
+                    error!("Session Cookie Error: {:#?}", e);
+                }
+            }
+            return;
+        }
+
+        // New sessions are only stored once they hold data, so clients that never log in don't fill the store.
+        if state.is_new && !state.modified {
+            return;
+        }
+        state.record.last_access = SystemTime::now();
+        if let Err(e) = self.store.save(&state.id, &state.record) {
+            error!("Session Save Error: {:#?}", e);
+            return;
+        }
+        if state.is_new {
+            if let Err(e) = response.add_cookie(self.cookie(&state.id)) {
+                error!("Session Cookie Error: {:#?}", e);
+            }
+        }
+    }
+
+    fn cookie(&self, value: &str) -> Cookie {
+        let mut builder = Cookie::build(&self.config.cookie_name, value)
+            .path(&self.config.cookie_path)
+            .http_only(true)
+            .secure(self.config.secure)
+            .same_site(self.config.same_site);
+        if let Some(ref domain) = self.config.cookie_domain {
+            builder = builder.domain(domain);
+        }
+        builder.finish()
+    }
+}
+
+// Generate a random, unguessable session ID.
+fn generate_session_id() -> String {
+    encode_url_safe(&random_bytes(32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::store::MemoryStore;
+    use super::*;
+    use crate::communication::body::Body;
+    use std::io::Cursor;
+
+    fn manager(config: SessionConfig) -> SessionManager {
+        SessionManager::with_config(MemoryStore::new(Duration::from_secs(3600)), config)
+    }
+
+    // Load the session of a request carrying the given session ID, if any.
+    fn load_request(manager: &SessionManager, id: Option<&str>) -> Request {
+        let cookie = id
+            .map(|id| format!("Cookie: sid={}\r\n", id))
+            .unwrap_or_default();
+        let raw = format!("GET / HTTP/1.1\r\nHost: a\r\n{}\r\n", cookie);
+        let reader = Body::shared_reader(Cursor::new(raw.into_bytes()));
+        let mut request = Request::read_request(&reader).unwrap();
+        manager.load(&mut request);
+        request
+    }
+
+    fn commit(manager: &SessionManager, request: &Request) -> Response {
+        let mut response = Response::new();
+        manager.commit(request, &mut response);
+        response
+    }
+
+    #[test]
+    fn session_is_stored_once_it_holds_data() {
+        let manager = manager(SessionConfig::default());
+        let request = load_request(&manager, None);
+        assert!(request.session().unwrap().is_new());
+        assert_eq!(commit(&manager, &request).header("Set-Cookie"), None);
+
+        request.session().unwrap().insert("user", "alice");
+        let id = request.session().unwrap().id();
+        let cookie = commit(&manager, &request);
+        let cookie = cookie.header("Set-Cookie").unwrap();
+        assert!(cookie.starts_with(&format!("sid={}; Path=/", id)));
+        assert!(cookie.contains("HttpOnly"));
+
+        let request = load_request(&manager, Some(&id));
+        let session = request.session().unwrap();
+        assert!(!session.is_new());
+        assert_eq!(session.get("user").as_deref(), Some("alice"));
+        assert_eq!(commit(&manager, &request).header("Set-Cookie"), None);
+    }
+
+    #[test]
+    fn unknown_ids_get_a_new_session() {
+        let manager = manager(SessionConfig::default());
+        let request = load_request(&manager, Some("forged"));
+        let session = request.session().unwrap();
+        assert!(session.is_new());
+        assert_ne!(session.id(), "forged");
+    }
+
+    #[test]
+    fn idle_and_absolute_timeouts_expire_sessions() {
+        for (idle, absolute) in [(Some(Duration::ZERO), None), (None, Some(Duration::ZERO))] {
+            let manager = manager(SessionConfig {
+                idle_timeout: idle,
+                absolute_timeout: absolute,
+                ..SessionConfig::default()
+            });
+            let request = load_request(&manager, None);
+            request.session().unwrap().insert("user", "alice");
+            let id = request.session().unwrap().id();
+            commit(&manager, &request);
+
+            std::thread::sleep(Duration::from_millis(5));
+            let request = load_request(&manager, Some(&id));
+            assert!(request.session().unwrap().is_new());
+            assert_eq!(manager.store.load(&id).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn regenerate_keeps_data_under_a_new_id() {
+        let manager = manager(SessionConfig::default());
+        let request = load_request(&manager, None);
+        request.session().unwrap().insert("user", "alice");
+        let old_id = request.session().unwrap().id();
+        commit(&manager, &request);
+
+        let request = load_request(&manager, Some(&old_id));
+        request.session().unwrap().regenerate();
+        let new_id = request.session().unwrap().id();
+        assert_ne!(new_id, old_id);
+        let response = commit(&manager, &request);
+        assert!(response
+            .header("Set-Cookie")
+            .unwrap()
+            .starts_with(&format!("sid={};", new_id)));
+
+        assert_eq!(manager.store.load(&old_id).unwrap(), None);
+        let request = load_request(&manager, Some(&new_id));
+        assert_eq!(
+            request.session().unwrap().get("user").as_deref(),
+            Some("alice")
+        );
+    }
+
+    #[test]
+    fn destroy_removes_the_session_and_cookie() {
+        let manager = manager(SessionConfig::default());
+        let request = load_request(&manager, None);
+        request.session().unwrap().insert("user", "alice");
+        let id = request.session().unwrap().id();
+        commit(&manager, &request);
+
+        let request = load_request(&manager, Some(&id));
+        request.session().unwrap().destroy();
+        let response = commit(&manager, &request);
+        assert!(response
+            .header("Set-Cookie")
+            .unwrap()
+            .starts_with("sid=; Path=/; Max-Age=0"));
+        assert_eq!(manager.store.load(&id).unwrap(), None);
+    }
+
+    #[test]
+    fn session_ids_are_random() {
+        let a = generate_session_id();
+        assert_eq!(a.len(), 43);
+        assert_ne!(a, generate_session_id());
+    }
+}
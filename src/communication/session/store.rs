@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::SessionRecord;
+use crate::utils::base64::{decode_url_safe, encode_url_safe};
+use crate::utils::crypto::{random_bytes, sha256};
+
+// Storage backend for sessions. Implementations must be safe to share between worker threads.
+pub trait SessionStore: Send + Sync {
+    // Load a session, returning None if it does not exist or has expired.
+    fn load(&self, id: &str) -> Result<Option<SessionRecord>, io::Error>;
+
+    // Insert or replace a session.
+    fn save(&self, id: &str, record: &SessionRecord) -> Result<(), io::Error>;
+
+    fn destroy(&self, id: &str) -> Result<(), io::Error>;
+
+    // Remove all expired sessions.
+    fn cleanup(&self) -> Result<(), io::Error>;
+}
+
+// Check if a record has not been accessed within the time to live.
+fn is_expired(record: &SessionRecord, ttl: Duration) -> bool {
+    SystemTime::now()
+        .duration_since(record.last_access)
+        .unwrap_or_default()
+        > ttl
+}
+
+// Session store keeping sessions in memory. Sessions are lost when the server restarts.
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, SessionRecord>>,
+    ttl: Duration,
+}
+
+impl MemoryStore {
+    // Create a store that expires sessions not accessed within the time to live.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Result<Option<SessionRecord>, io::Error> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(id) {
+            Some(record) if is_expired(record, self.ttl) => {
+                sessions.remove(id);
+                Ok(None)
+            }
+            record => Ok(record.cloned()),
+        }
+    }
+
+    fn save(&self, id: &str, record: &SessionRecord) -> Result<(), io::Error> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), record.clone());
+        Ok(())
+    }
+
+    fn destroy(&self, id: &str) -> Result<(), io::Error> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    fn cleanup(&self) -> Result<(), io::Error> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, record| !is_expired(record, self.ttl));
+        Ok(())
+    }
+}
+
+// Session store keeping one file per session in a directory, so sessions survive restarts.
+pub struct FileStore {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl FileStore {
+    // Create a store in the directory (created if missing) that expires sessions not accessed within the time to live. On Unix a missing directory is created readable by the owner only.
+    pub fn new(dir: impl Into<PathBuf>, ttl: Duration) -> Result<Self, io::Error> {
+        let dir = dir.into();
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&dir)?;
+        Ok(Self { dir, ttl })
+    }
+
+    // Session files are named by the hash of the ID, so IDs never end up in paths or directory listings.
+    fn path(&self, id: &str) -> PathBuf {
+        let name: String = sha256(id.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        self.dir.join(format!("{}.session", name))
+    }
+
+    fn read(path: &Path) -> Result<Option<SessionRecord>, io::Error> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Self::deserialize(&content)
+            .map(Some)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Corrupt session file"))
+    }
+
+    // Format: creation and last access time in seconds since the epoch, followed by one base64 encoded key-value pair per line.
+    fn serialize(record: &SessionRecord) -> String {
+        let secs = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        };
+        let mut content = format!("{}\n{}\n", secs(record.created), secs(record.last_access));
+        for (key, value) in &record.data {
+            content.push_str(&format!(
+                "{} {}\n",
+                encode_url_safe(key.as_bytes()),
+                encode_url_safe(value.as_bytes())
+            ));
+        }
+        content
+    }
+
+    fn deserialize(content: &str) -> Option<SessionRecord> {
+        let mut lines = content.lines();
+        let mut time = || {
+            let secs = lines.next()?.parse::<u64>().ok()?;
+            Some(UNIX_EPOCH + Duration::from_secs(secs))
+        };
+        let created = time()?;
+        let last_access = time()?;
+        let mut data = HashMap::new();
+        for line in lines {
+            let (key, value) = line.split_once(' ')?;
+            let decode = |s: &str| String::from_utf8(decode_url_safe(s)?).ok();
+            data.insert(decode(key)?, decode(value)?);
+        }
+        Some(SessionRecord {
+            data,
+            created,
+            last_access,
+        })
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> Result<Option<SessionRecord>, io::Error> {
+        let path = self.path(id);
+        match Self::read(&path)? {
+            Some(record) if is_expired(&record, self.ttl) => {
+                fs::remove_file(path)?;
+                Ok(None)
+            }
+            record => Ok(record),
+        }
+    }
+
+    fn save(&self, id: &str, record: &SessionRecord) -> Result<(), io::Error> {
+        // Write to a uniquely named temporary file and rename, so a crash or a concurrent save never leaves a half written session behind.
+        let path = self.path(id);
+        let tmp_path = path.with_extension(format!("{}.tmp", encode_url_safe(&random_bytes(6))));
+        // Session data must not be readable by other local users, so on Unix the file is created with 0600.
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let written = options
+            .open(&tmp_path)
+            .and_then(|mut file| file.write_all(Self::serialize(record).as_bytes()));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        fs::rename(tmp_path, path)
+    }
+
+    fn destroy(&self, id: &str) -> Result<(), io::Error> {
+        match fs::remove_file(self.path(id)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn cleanup(&self) -> Result<(), io::Error> {
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_none_or(|extension| extension != "session")
+            {
+                continue;
+            }
+            // Corrupt files are removed as well, as they can never be loaded.
+            let expired = match Self::read(&path) {
+                Ok(Some(record)) => is_expired(&record, self.ttl),
+                Ok(None) => false,
+                Err(_) => true,
+            };
+            if expired {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_accessed(ago: Duration) -> SessionRecord {
+        let mut record = SessionRecord::new();
+        record
+            .data
+            .insert(String::from("user"), String::from("alice"));
+        record
+            .data
+            .insert(String::from("note"), String::from("a b\nc=d"));
+        record.last_access = SystemTime::now() - ago;
+        record
+    }
+
+    // A fresh directory under the system temp directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!(
+                "tiny-http-sessions-{}",
+                encode_url_safe(&random_bytes(9))
+            )))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn memory_store_expires_sessions() {
+        let store = MemoryStore::new(Duration::from_secs(60));
+        let fresh = record_accessed(Duration::ZERO);
+        store.save("fresh", &fresh).unwrap();
+        store
+            .save("stale", &record_accessed(Duration::from_secs(120)))
+            .unwrap();
+        assert_eq!(store.len(), 2);
+
+        assert_eq!(store.load("fresh").unwrap(), Some(fresh));
+        assert_eq!(store.load("stale").unwrap(), None);
+        assert_eq!(store.load("missing").unwrap(), None);
+        assert_eq!(store.len(), 1);
+
+        store
+            .save("stale", &record_accessed(Duration::from_secs(120)))
+            .unwrap();
+        store.cleanup().unwrap();
+        assert_eq!(store.len(), 1);
+        store.destroy("fresh").unwrap();
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn file_store_round_trips_and_expires_sessions() {
+        let dir = TempDir::new();
+        let store = FileStore::new(&dir.0, Duration::from_secs(60)).unwrap();
+        let record = record_accessed(Duration::ZERO);
+        store.save("id", &record).unwrap();
+        let loaded = store.load("id").unwrap().unwrap();
+        assert_eq!(loaded.data, record.data);
+        // Times are stored with second precision.
+        assert_eq!(
+            loaded.created.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            record.created.duration_since(UNIX_EPOCH).unwrap().as_secs()
+        );
+
+        store
+            .save("stale", &record_accessed(Duration::from_secs(120)))
+            .unwrap();
+        assert_eq!(store.load("stale").unwrap(), None);
+        assert!(!store.path("stale").exists());
+
+        store.destroy("id").unwrap();
+        store.destroy("id").unwrap();
+        assert_eq!(store.load("id").unwrap(), None);
+    }
+
+    #[test]
+    fn file_store_cleanup_removes_expired_and_corrupt_files() {
+        let dir = TempDir::new();
+        let store = FileStore::new(&dir.0, Duration::from_secs(60)).unwrap();
+        store
+            .save("fresh", &record_accessed(Duration::ZERO))
+            .unwrap();
+        store
+            .save("stale", &record_accessed(Duration::from_secs(120)))
+            .unwrap();
+        fs::write(store.path("corrupt"), "not a session").unwrap();
+        fs::write(dir.0.join("other.txt"), "kept").unwrap();
+        assert!(store.load("corrupt").is_err());
+
+        store.cleanup().unwrap();
+        assert!(store.path("fresh").exists());
+        assert!(!store.path("stale").exists());
+        assert!(!store.path("corrupt").exists());
+        assert!(dir.0.join("other.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_store_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        let store = FileStore::new(&dir.0, Duration::from_secs(60)).unwrap();
+        store.save("id", &record_accessed(Duration::ZERO)).unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&store.path("id")), 0o600);
+        assert_eq!(mode(&dir.0), 0o700);
+    }
+}
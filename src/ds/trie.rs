@@ -23,6 +23,15 @@ pub struct Trie<T> {
     root: Node<T>,
 }
 
+impl<T> Default for Trie<T>
+where
+    T: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> Trie<T>
 where
     T: Clone,
@@ -54,4 +63,15 @@ where
         }
         Some(node.value.clone().unwrap())
     }
+
+    // All the values in the trie, in no particular order.
+    pub fn values(&self) -> Vec<&T> {
+        let mut values = Vec::new();
+        let mut nodes = vec![&self.root];
+        while let Some(node) = nodes.pop() {
+            values.extend(node.value.as_ref());
+            nodes.extend(node.children.values());
+        }
+        values
+    }
 }
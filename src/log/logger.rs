@@ -1,45 +1,233 @@
 use std::{
     env,
+    fmt::{self, Display, Formatter},
     fs::File,
-    io::Write,
-    sync::{Mutex, Once},
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Mutex, Once, OnceLock, RwLock,
+    },
     time::SystemTime,
 };
 
 static INIT_LOGGER: Once = Once::new();
-pub static mut LOGGER: Option<Mutex<Logger>> = None;
+pub static LOGGER: OnceLock<Mutex<Logger>> = OnceLock::new();
+
+// Minimum level of messages that are written, for modules without a level of their own.
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+// Minimum levels of modules and their submodules, by module path.
+static MODULE_LEVELS: RwLock<Vec<(String, Level)>> = RwLock::new(Vec::new());
+// Whether MODULE_LEVELS has entries, so logging doesn't take its lock when it's empty.
+static HAS_MODULE_LEVELS: AtomicBool = AtomicBool::new(false);
+
+// Log a message at a level. The message is only formatted if the level is enabled for the calling module.
+#[macro_export]
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::log::logger::Logger::enabled($level, module_path!()) {
+            if let Some(logger) = $crate::log::logger::LOGGER.get() {
+                logger
+                    .lock()
+                    .unwrap()
+                    .write($level, module_path!(), &format_args!($($arg)*).to_string());
+            }
+        }
+    };
+}
 
 #[macro_export]
 macro_rules! log {
     ($($arg:tt)*) => {
-        unsafe {
-            if let Some(logger) = &crate::log::logger::LOGGER {
-                logger.lock().unwrap().log(&format_args!($($arg)*).to_string());
-           }
-        }
+        $crate::log_at!($crate::log::logger::Level::Info, $($arg)*)
     };
 }
 
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log_at!($crate::log::logger::Level::Error, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::log_at!($crate::log::logger::Level::Warn, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log_at!($crate::log::logger::Level::Info, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log_at!($crate::log::logger::Level::Debug, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        $crate::log_at!($crate::log::logger::Level::Trace, $($arg)*)
+    };
+}
+
+// Severity of a log message, from most to least severe. A minimum level lets through messages at that level and the more severe ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    const ALL: [Level; 5] = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    // Parse a level name in any case, e.g. "debug".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|level| level.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown log level: {}", s))
+    }
+}
+
+// Where log messages are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogDestination {
+    // File created at the path, relative to the current directory unless absolute.
+    File(PathBuf),
+    Stdout,
+    Stderr,
+    Disabled,
+}
+
+impl Default for LogDestination {
+    fn default() -> Self {
+        LogDestination::File(PathBuf::from("log.txt"))
+    }
+}
+
 // Singleton logger.
 pub struct Logger {
-    pub log_file: File,
+    pub output: Box<dyn Write + Send>,
     pub start_time: SystemTime,
 }
 
 impl Logger {
     pub fn init(log_file_path: &str) {
+        Self::init_with(&LogDestination::File(PathBuf::from(log_file_path)));
+    }
+
+    // Initialize the logger with the given destination. Only the first call has an effect, later ones keep the existing logger.
+    pub fn init_with(destination: &LogDestination) {
         INIT_LOGGER.call_once(|| {
-            let mut path = env::current_dir().unwrap();
-            path.push(log_file_path);
-            unsafe {
-                LOGGER = Some(Mutex::new(Logger {
-                    log_file: File::create(path).unwrap(),
-                    start_time: SystemTime::now(),
-                }))
+            let output: Box<dyn Write + Send> = match destination {
+                LogDestination::File(path) => {
+                    match File::create(env::current_dir().unwrap_or_default().join(path)) {
+                        Ok(file) => Box::new(file),
+                        Err(e) => {
+                            println!("LOG ERROR: {e}");
+                            return;
+                        }
+                    }
+                }
+                LogDestination::Stdout => Box::new(io::stdout()),
+                LogDestination::Stderr => Box::new(io::stderr()),
+                LogDestination::Disabled => return,
             };
+            let _ = LOGGER.set(Mutex::new(Logger {
+                output,
+                start_time: SystemTime::now(),
+            }));
         });
     }
 
+    // Write out anything the log destination buffers, e.g. before the process exits.
+    pub fn flush() {
+        if let Some(logger) = LOGGER.get() {
+            let _ = logger.lock().unwrap().output.flush();
+        }
+    }
+
+    // Set the minimum level of messages that are written. Can be changed at any time.
+    pub fn set_level(level: Level) {
+        MIN_LEVEL.store(level as u8, Ordering::Relaxed);
+    }
+
+    // The minimum level of messages that are written, for modules without a level of their own.
+    pub fn level() -> Level {
+        Level::ALL[MIN_LEVEL.load(Ordering::Relaxed) as usize]
+    }
+
+    // Set the minimum level of a module and its submodules, e.g. "tiny_rust_server::communication::session". The most specific module level applies.
+    pub fn set_module_level(module: &str, level: Level) {
+        let mut module_levels = MODULE_LEVELS.write().unwrap();
+        module_levels.retain(|(path, _)| path != module);
+        module_levels.push((module.to_string(), level));
+        HAS_MODULE_LEVELS.store(true, Ordering::Relaxed);
+    }
+
+    // Make a module use the minimum level again.
+    pub fn clear_module_level(module: &str) {
+        let mut module_levels = MODULE_LEVELS.write().unwrap();
+        module_levels.retain(|(path, _)| path != module);
+        HAS_MODULE_LEVELS.store(!module_levels.is_empty(), Ordering::Relaxed);
+    }
+
+    // Whether messages at the level are written for the module.
+    pub fn enabled(level: Level, module: &str) -> bool {
+        if !HAS_MODULE_LEVELS.load(Ordering::Relaxed) {
+            return level <= Self::level();
+        }
+        let module_levels = MODULE_LEVELS.read().unwrap();
+        let min_level = module_levels
+            .iter()
+            .filter(|(path, _)| {
+                module
+                    .strip_prefix(path.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(path, _)| path.len())
+            .map_or_else(Self::level, |(_, level)| *level);
+        level <= min_level
+    }
+
     // Get the time since the logger was initialized.
     pub fn get_time_since_start(&self) -> String {
         let since_start = SystemTime::now()
@@ -48,14 +236,83 @@ impl Logger {
         since_start.as_secs().to_string()
     }
 
-    // Log a message to the log file.
+    // Log a message to the log file at the info level.
     pub fn log(&mut self, message: &str) {
-        if let Err(e) = write!(
-            self.log_file,
-            "{}",
-            format!("{}: {} \n", self.get_time_since_start(), message)
+        self.write(Level::Info, module_path!(), message);
+    }
+
+    // Log a message from a module to the log file. Levels are checked by the logging macros, not here.
+    pub fn write(&mut self, level: Level, module: &str, message: &str) {
+        if let Err(e) = writeln!(
+            self.output,
+            "{}: {} [{}] {} ",
+            self.get_time_since_start(),
+            level,
+            module,
+            message
         ) {
             println!("LOG ERROR: {e}");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The levels are global, so tests that change them run one at a time.
+    static LEVELS: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn levels_parse_in_any_case() {
+        assert_eq!("debug".parse(), Ok(Level::Debug));
+        assert_eq!("WARN".parse(), Ok(Level::Warn));
+        assert_eq!("Trace".parse(), Ok(Level::Trace));
+        assert_eq!(
+            "verbose".parse::<Level>(),
+            Err(String::from("Unknown log level: verbose"))
+        );
+        for level in Level::ALL {
+            assert_eq!(level.as_str().parse(), Ok(level));
+        }
+    }
+
+    #[test]
+    fn minimum_level_lets_through_more_severe_messages() {
+        let _guard = LEVELS.lock().unwrap();
+        assert!(Level::Error < Level::Warn && Level::Debug < Level::Trace);
+        Logger::set_level(Level::Warn);
+        assert_eq!(Logger::level(), Level::Warn);
+        assert!(Logger::enabled(Level::Error, "app"));
+        assert!(Logger::enabled(Level::Warn, "app"));
+        assert!(!Logger::enabled(Level::Info, "app"));
+        Logger::set_level(Level::Trace);
+        assert!(Logger::enabled(Level::Trace, "app"));
+        Logger::set_level(Level::Info);
+    }
+
+    #[test]
+    fn most_specific_module_level_applies() {
+        let _guard = LEVELS.lock().unwrap();
+        Logger::set_module_level("app", Level::Error);
+        Logger::set_module_level("app::db", Level::Debug);
+        assert!(!Logger::enabled(Level::Warn, "app"));
+        assert!(!Logger::enabled(Level::Warn, "app::http"));
+        assert!(Logger::enabled(Level::Debug, "app::db"));
+        assert!(Logger::enabled(Level::Debug, "app::db::pool"));
+        assert!(!Logger::enabled(Level::Trace, "app::db::pool"));
+        // Modules only match whole path segments.
+        assert!(!Logger::enabled(Level::Debug, "app::dbx"));
+        assert!(Logger::enabled(Level::Info, "application"));
+
+        // Setting a module again replaces its level.
+        Logger::set_module_level("app::db", Level::Warn);
+        assert!(!Logger::enabled(Level::Info, "app::db"));
+
+        Logger::clear_module_level("app::db");
+        assert!(!Logger::enabled(Level::Warn, "app::db"));
+        Logger::clear_module_level("app");
+        assert!(Logger::enabled(Level::Info, "app::db"));
+        assert!(!Logger::enabled(Level::Debug, "app::db"));
+    }
+}
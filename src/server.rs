@@ -1,101 +1,830 @@
-use crate::communication::request::{Request, StaticRequestData};
+mod classifier;
+pub mod handle;
 
+use crate::communication::body::{Body, SharedReader};
+use crate::communication::protocol::{RequestLimits, Version};
+use crate::communication::request::{ParseMode, Request, StaticRequestData};
+
+use self::classifier::Classifier;
+use self::handle::ServerHandle;
 use crate::communication::response::Response;
 use crate::communication::router::Router;
 use crate::ds::trie::Trie;
-use crate::log;
-use crate::log::logger::Logger;
+use crate::log::logger::{LogDestination, Logger};
+use crate::utils::executor::Executor;
 use crate::utils::file::get_first_html_file_name;
-use crate::utils::general::is_static_file;
+use crate::utils::general::{is_static_file, panic_message};
 use crate::utils::guess::guess_mime_type;
-use crate::utils::thread_pool::ThreadPool;
+use crate::utils::signal;
+use crate::utils::stream::{ReadTimeout, TimedStream};
+use crate::utils::thread_pool::{PoolConfig, PoolStats, Priority, Spawner, ThreadPool};
 
+use std::cell::Cell;
 use std::env::current_dir;
 use std::error::Error;
-use std::fmt::{self, Display, Formatter};
 use std::fs::read_to_string;
-use std::net::{TcpListener, TcpStream};
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::io::{self, BufRead, BufWriter, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::str;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Maximum number of unread request body bytes discarded to keep a connection open. Connections with larger unread bodies are closed instead.
+const MAX_DRAIN_SIZE: usize = 64 * 1024;
+
+// How often waiting connections and the signal watcher check whether the server is stopping.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long writing a 503 response to a connection the server has no room for may take.
+const REJECT_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// How long unread input is discarded after an error response before the connection is closed.
+const LINGER_TIMEOUT: Duration = Duration::from_secs(1);
 
 // This is the main entry point for the server.
 pub struct Server {
     thread_pool: ThreadPool,
+    executor: Executor,
     listener: TcpListener,
+    local_addr: SocketAddr,
     routers: Arc<Mutex<Trie<Router>>>,
-    _address: String,
-    root_path: String,
+    config: ServerConfig,
+    // Set once the server is asked to stop.
+    stopping: Arc<AtomicBool>,
+    counters: Arc<Counters>,
 }
 
-// Represents the server address, which is a tuple of an IP address and a port.
-struct Address {
-    ip: (u8, u8, u8, u8),
-    port: u16,
+// Settings of a server. Build a server with these using ServerBuilder.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    // Size range of the worker thread pool serving connections.
+    pub pool: PoolConfig,
+    pub keep_alive: KeepAlive,
+    pub limits: RequestLimits,
+    pub parse_mode: ParseMode,
+    pub timeouts: Timeouts,
+    // Size range of the thread pool running background jobs, kept apart from the workers serving connections.
+    pub background: PoolConfig,
+    // What to do with new connections while the queue of connections waiting for a worker is full.
+    pub overload: OverloadPolicy,
+    // Where the server logs to. The logger is shared by all servers in the process, so only the first server's destination is used.
+    pub log_destination: LogDestination,
+    // Directory that static file directories are resolved against.
+    pub static_root: PathBuf,
+    // Shut down gracefully on SIGINT and SIGTERM, or Ctrl+C and Ctrl+Break on Windows. A second signal exits immediately. On other platforms a warning is logged and signals keep their default behavior.
+    pub handle_signals: bool,
+    // How long requests in progress may take to finish once the server stops.
+    pub shutdown_timeout: Duration,
 }
 
-impl Display for Address {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}.{}.{}.{}:{}",
-            self.ip.0, self.ip.1, self.ip.2, self.ip.3, self.port
-        )
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            pool: PoolConfig::default(),
+            background: PoolConfig {
+                min_threads: 1,
+                max_threads: 4,
+                // Background jobs all go in the normal lane.
+                critical_workers: 0,
+                thread_name: "tiny-http-background",
+                ..Default::default()
+            },
+            overload: OverloadPolicy::default(),
+            keep_alive: KeepAlive::default(),
+            limits: RequestLimits::default(),
+            parse_mode: ParseMode::default(),
+            timeouts: Timeouts::default(),
+            log_destination: LogDestination::default(),
+            static_root: current_dir().unwrap_or_default(),
+            handle_signals: false,
+            shutdown_timeout: Duration::from_secs(30),
+        }
     }
 }
 
-impl Server {
-    pub fn new(ip: (u8, u8, u8, u8), port: u16) -> Result<Server, Box<dyn Error>> {
-        let _address: String = Address { ip, port }.to_string();
-        match TcpListener::bind(&_address) {
+// Builder for a server with custom settings.
+//
+// let server = ServerBuilder::new()
+//     .bind("[::1]:8080")
+//     .threads(16)
+//     .log_destination(LogDestination::Stderr)
+//     .build()?;
+#[derive(Debug, Default)]
+pub struct ServerBuilder {
+    addresses: Vec<SocketAddr>,
+    // Error resolving an address, reported by build.
+    error: Option<io::Error>,
+    config: ServerConfig,
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add addresses to bind to, e.g. "0.0.0.0:80", "127.0.0.1:0" for any free port, "[::]:80", "localhost:8080" or a SocketAddr. The server listens on the first one that can be bound.
+    pub fn bind<A: ToSocketAddrs>(mut self, address: A) -> Self {
+        match address.to_socket_addrs() {
+            Ok(addresses) => self.addresses.extend(addresses),
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+        self
+    }
+
+    // Replace all settings at once.
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    // Use a fixed number of worker threads.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.config.pool = PoolConfig::fixed(threads);
+        self
+    }
+
+    // Let the number of worker threads grow and shrink with the load.
+    pub fn pool(mut self, pool: PoolConfig) -> Self {
+        self.config.pool = pool;
+        self
+    }
+
+    pub fn background(mut self, background: PoolConfig) -> Self {
+        self.config.background = background;
+        self
+    }
+
+    pub fn overload(mut self, overload: OverloadPolicy) -> Self {
+        self.config.overload = overload;
+        self
+    }
+
+    pub fn keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        self.config.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn limits(mut self, limits: RequestLimits) -> Self {
+        self.config.limits = limits;
+        self
+    }
+
+    pub fn parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.config.parse_mode = parse_mode;
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.config.timeouts = timeouts;
+        self
+    }
+
+    pub fn log_destination(mut self, log_destination: LogDestination) -> Self {
+        self.config.log_destination = log_destination;
+        self
+    }
+
+    pub fn static_root<P: Into<PathBuf>>(mut self, static_root: P) -> Self {
+        self.config.static_root = static_root.into();
+        self
+    }
+
+    // Shut down gracefully on SIGINT and SIGTERM (Ctrl+C and Ctrl+Break on Windows). Closing the console window on Windows still ends the process right away.
+    pub fn handle_signals(mut self, handle_signals: bool) -> Self {
+        self.config.handle_signals = handle_signals;
+        self
+    }
+
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.config.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    // Bind the listener and start the worker threads.
+    pub fn build(self) -> Result<Server, Box<dyn Error>> {
+        if let Some(e) = self.error {
+            return Err(Box::new(e));
+        }
+        if self.addresses.is_empty() {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No address to bind to",
+            )));
+        }
+        let pool = self.config.pool;
+        let background = self.config.background;
+        if [pool, background]
+            .iter()
+            .any(|pool| pool.max_threads == 0 || pool.min_threads > pool.max_threads)
+        {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Thread count must be greater than 0 and the minimum must not exceed the maximum",
+            )));
+        }
+        if [pool, background]
+            .iter()
+            .any(|pool| pool.queue_capacity == 0)
+        {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Queue capacity must be greater than 0",
+            )));
+        }
+        if [pool, background]
+            .iter()
+            .any(|pool| pool.starvation_limit == 0)
+        {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Starvation limit must be greater than 0",
+            )));
+        }
+        Logger::init_with(&self.config.log_destination);
+        match TcpListener::bind(&self.addresses[..]) {
             Ok(listener) => {
-                Logger::init("log.txt");
-                log!("Server listening on: {}", _address);
-                return Ok(Server {
-                    thread_pool: ThreadPool::new(5),
+                let local_addr = listener.local_addr()?;
+                info!("Server listening on: {}", local_addr);
+                Ok(Server {
+                    thread_pool: ThreadPool::with_config(pool),
+                    executor: Executor::new(background),
                     listener,
+                    local_addr,
                     routers: Arc::new(Mutex::new(Trie::new())),
-                    _address,
-                    root_path: current_dir().unwrap_or_default().display().to_string(),
-                });
+                    config: self.config,
+                    stopping: Arc::new(AtomicBool::new(false)),
+                    counters: Arc::new(Counters::default()),
+                })
             }
             Err(e) => {
-                log!("Listener Error: {:#?}", e);
-                return Err(Box::new(e));
+                error!("Listener Error: {:#?}", e);
+                Err(Box::new(e))
             }
-        };
+        }
+    }
+}
+
+// Snapshot of a server's activity, see Server::stats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    // The worker pool serving connections.
+    pub pool: PoolStats,
+    // Connections accepted since the server started, including shed ones.
+    pub accepted: u64,
+    // Connections being served by a worker.
+    pub open_connections: usize,
+    // Connections rejected or dropped because the server was overloaded.
+    pub shed: u64,
+    // Requests whose route panicked.
+    pub route_panics: u64,
+}
+
+// Counters behind ServerStats, shared with the workers and server handles.
+#[derive(Debug, Default)]
+struct Counters {
+    accepted: AtomicU64,
+    open_connections: AtomicUsize,
+    shed: AtomicU64,
+    route_panics: AtomicU64,
+}
+
+impl Counters {
+    fn stats(&self, pool: PoolStats) -> ServerStats {
+        ServerStats {
+            pool,
+            accepted: self.accepted.load(Ordering::Relaxed),
+            open_connections: self.open_connections.load(Ordering::Relaxed),
+            shed: self.shed.load(Ordering::Relaxed),
+            route_panics: self.route_panics.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// What a server handle reads the stats of its server from.
+struct StatsSource {
+    pool: Spawner,
+    counters: Arc<Counters>,
+}
+
+impl StatsSource {
+    fn stats(&self) -> ServerStats {
+        self.counters.stats(self.pool.stats())
+    }
+}
+
+// What the acceptor and the classifier need to hand connections to the workers.
+#[derive(Clone)]
+struct Dispatcher {
+    spawner: Spawner,
+    routers: Arc<Mutex<Trie<Router>>>,
+    config: Arc<ServerConfig>,
+    stopping: Arc<AtomicBool>,
+    counters: Arc<Counters>,
+}
+
+impl Dispatcher {
+    // Queue a connection in the lane of the priority, or shed it by the overload policy if the queue is full. Only one thread dispatches connections, the acceptor or the classifier, so the queue can't fill up between the check and queuing.
+    fn dispatch(&self, stream: TcpStream, priority: Priority) {
+        if self.config.overload != OverloadPolicy::Block && self.spawner.is_full() {
+            self.counters.shed.fetch_add(1, Ordering::Relaxed);
+            if let OverloadPolicy::Reject { retry_after } = self.config.overload {
+                Server::reject_overloaded(&stream, retry_after);
+            }
+            return;
+        }
+        let dispatcher = self.clone();
+        // The pool only refuses jobs once it is shut down, which happens after connections stop being dispatched.
+        let _ = self.spawner.execute_with(priority, move || {
+            Server::handle_connection(
+                &dispatcher.routers,
+                stream,
+                &dispatcher.config,
+                &dispatcher.stopping,
+                &dispatcher.counters,
+            )
+        });
+    }
+}
+
+// Counts a connection as open while it is being served, including when a route panics.
+struct OpenConnection<'a>(&'a Counters);
+
+impl<'a> OpenConnection<'a> {
+    fn new(counters: &'a Counters) -> Self {
+        counters.open_connections.fetch_add(1, Ordering::Relaxed);
+        Self(counters)
+    }
+}
+
+impl Drop for OpenConnection<'_> {
+    fn drop(&mut self) {
+        self.0.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// What the server does with a new connection when its queue of connections waiting for a worker is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverloadPolicy {
+    // Stop accepting until there is room, leaving new connections in the listen backlog.
+    #[default]
+    Block,
+    // Answer with 503 Service Unavailable and the Retry-After header, then close the connection.
+    Reject {
+        retry_after: Duration,
+    },
+    // Close the connection without a response.
+    Drop,
+}
+
+// Settings for persistent (keep-alive) connections.
+#[derive(Debug, Clone, Copy)]
+pub struct KeepAlive {
+    pub enabled: bool,
+    // How long an idle connection is kept open waiting for the next request.
+    pub idle_timeout: Duration,
+    // Maximum number of requests served on one connection before it is closed.
+    pub max_requests: usize,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_timeout: Duration::from_secs(5),
+            max_requests: 100,
+        }
+    }
+}
+
+// Timeouts applied to each connection, so slow or stalled clients can't hold a worker thread.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    // How long a single read of the request head may wait. Also the time a new connection has to start sending.
+    pub header_read: Duration,
+    // Deadline for the whole request head, which stops clients trickling in headers a byte at a time.
+    pub header_total: Duration,
+    // How long a single read of the request body may wait.
+    pub body_read: Duration,
+    // How long a single write of the response may wait.
+    pub write: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            header_read: Duration::from_secs(10),
+            header_total: Duration::from_secs(30),
+            body_read: Duration::from_secs(30),
+            write: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Server {
+    // Create a server listening on an IPv4 address with the default settings. Port 0 lets the OS pick a free port, see local_addr. Use ServerBuilder for other settings.
+    pub fn new(ip: (u8, u8, u8, u8), port: u16) -> Result<Server, Box<dyn Error>> {
+        ServerBuilder::new()
+            .bind((Ipv4Addr::new(ip.0, ip.1, ip.2, ip.3), port))
+            .build()
+    }
+
+    // Get a builder for a server with custom settings.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
     }
 
+    // The address the server is listening on, with the actual port when bound to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    // Number of worker threads alive.
+    pub fn pool_size(&self) -> usize {
+        self.thread_pool.size()
+    }
+
+    // Number of accepted connections waiting for a worker thread.
+    pub fn queue_depth(&self) -> usize {
+        self.thread_pool.queue_depth()
+    }
+
+    // Number of connections rejected or dropped because the server was overloaded.
+    pub fn shed_count(&self) -> u64 {
+        self.counters.shed.load(Ordering::Relaxed)
+    }
+
+    // Snapshot of the server's activity. The counters are read one by one, so they may be slightly out of step with each other.
+    pub fn stats(&self) -> ServerStats {
+        self.counters.stats(self.thread_pool.stats())
+    }
+
+    // Executor for background jobs, which runs until the server shuts down. Clones can be moved into routes to start jobs after responding.
+    pub fn executor(&self) -> Executor {
+        self.executor.clone()
+    }
+
+    // The settings of the server.
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    // Serve connections on the current thread. This blocks until the server is stopped by a signal, then waits up to the shutdown timeout for requests in progress. The server can't be run again after that.
     pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        self.accept()?;
+        self.finish_shutdown(Some(self.config.shutdown_timeout));
+        Ok(())
+    }
+
+    // Wait for the connections and background jobs of a stopped server to finish and flush the log. Returns false if the timeout passed first.
+    fn finish_shutdown(&mut self, timeout: Option<Duration>) -> bool {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        // Requests finish first, as they may still start background jobs.
+        let connections_finished = self.thread_pool.shutdown(timeout);
+        let jobs_finished = self
+            .executor
+            .shutdown(deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())));
+        let finished = connections_finished && jobs_finished;
+        if finished {
+            info!("Server shut down");
+        } else {
+            warn!("Server shut down with connections or background jobs still running after the shutdown timeout");
+        }
+        Logger::flush();
+        finished
+    }
+
+    // Serve connections on a background thread. The returned handle stops the server and waits for it.
+    pub fn spawn(mut self) -> Result<ServerHandle, Box<dyn Error>> {
+        let stopping = self.stopping.clone();
+        let local_addr = self.local_addr;
+        let stats = StatsSource {
+            pool: self.thread_pool.spawner(),
+            counters: self.counters.clone(),
+        };
+        let (done, finished) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name(String::from("tiny-http-acceptor"))
+            .spawn(move || {
+                let result = self.accept();
+                let _ = done.send(());
+                (self, result)
+            })?;
+        Ok(ServerHandle::new(
+            local_addr, stopping, stats, thread, finished,
+        ))
+    }
+
+    // Accept connections and hand them to the thread pool until the server is stopped.
+    fn accept(&mut self) -> io::Result<()> {
+        if self.config.handle_signals {
+            self.watch_signals()?;
+        }
+        let dispatcher = Dispatcher {
+            spawner: self.thread_pool.spawner(),
+            routers: self.routers.clone(),
+            config: Arc::new(self.config.clone()),
+            stopping: self.stopping.clone(),
+            counters: self.counters.clone(),
+        };
+        // Routes can be given a lane after their router is registered, so this is only looked at once the server starts.
+        let prioritized = self
+            .routers
+            .lock()
+            .unwrap()
+            .values()
+            .iter()
+            .any(|router| router.has_priorities());
+        // Connections need their request line looked at only if a route uses a lane other than the normal one.
+        let classifier = if prioritized {
+            Some(Classifier::start(dispatcher.clone())?)
+        } else {
+            None
+        };
         for stream in self.listener.incoming() {
-            let routers = self.routers.clone();
+            if self.stopping.load(Ordering::SeqCst) {
+                info!("Server stopped accepting connections");
+                break;
+            }
             match stream {
                 Ok(stream) => {
-                    self.thread_pool
-                        .execute(move || match Request::build_request(&stream) {
-                            Ok(mut request) => {
-                                Self::handle_loop(&routers, &stream, &mut request);
-                            }
-                            Err(e) => log!("Request Error: {:#?}", e),
-                        });
+                    self.counters.accepted.fetch_add(1, Ordering::Relaxed);
+                    match &classifier {
+                        Some(classifier) => classifier.classify(stream),
+                        None => dispatcher.dispatch(stream, Priority::Normal),
+                    }
                 }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
                 Err(e) => {
-                    log!("Stream Error: {:#?}", e);
-                    return Err(Box::new(e));
+                    error!("Stream Error: {:#?}", e);
+                    return Err(e);
                 }
             }
         }
+        // Connections still waiting for their request line are queued before the pool shuts down.
+        if let Some(classifier) = classifier {
+            classifier.finish();
+        }
         Ok(())
     }
 
+    // Answer a connection the server has no room for. This runs on the acceptor or classifier thread, so the write must not wait long.
+    fn reject_overloaded(stream: &TcpStream, retry_after: Duration) {
+        if stream
+            .set_write_timeout(Some(REJECT_WRITE_TIMEOUT))
+            .is_err()
+        {
+            return;
+        }
+        let mut response = Response::new();
+        response.set_status(503, "Service Unavailable");
+        response.set_header("Retry-After", &retry_after.as_secs().max(1).to_string());
+        response.set_header("Connection", "close");
+        if response.send(stream).is_ok() {
+            let _ = stream.shutdown(Shutdown::Write);
+        }
+    }
+
+    // Stop the server on SIGINT or SIGTERM (Ctrl+C or Ctrl+Break on Windows), and exit the process on a second signal. The watcher ends when the server is stopped some other way.
+    fn watch_signals(&self) -> io::Result<()> {
+        if !signal::install_shutdown_handlers() {
+            warn!(
+                "Shutdown signals are not supported on this platform, handle_signals has no effect"
+            );
+            return Ok(());
+        }
+        let stopping = self.stopping.clone();
+        let local_addr = self.local_addr;
+        let initial = signal::shutdown_signals();
+        thread::Builder::new()
+            .name(String::from("tiny-http-signals"))
+            .spawn(move || {
+                let mut stopped_by_signal = false;
+                loop {
+                    thread::sleep(STOP_POLL_INTERVAL);
+                    let received = signal::shutdown_signals() - initial;
+                    if received >= 2 {
+                        warn!("Second shutdown signal received, exiting");
+                        Logger::flush();
+                        process::exit(130);
+                    }
+                    if received == 1 && !stopped_by_signal {
+                        info!("Shutdown signal received, draining connections");
+                        stopped_by_signal = true;
+                        let _ = handle::stop(&stopping, local_addr);
+                    }
+                    if !stopped_by_signal && stopping.load(Ordering::SeqCst) {
+                        return;
+                    }
+                }
+            })?;
+        Ok(())
+    }
+
+    // Serve requests on a connection until the client or the keep-alive settings close it.
+    fn handle_connection(
+        routers: &Arc<Mutex<Trie<Router>>>,
+        stream: TcpStream,
+        config: &ServerConfig,
+        stopping: &AtomicBool,
+        counters: &Counters,
+    ) {
+        let _open = OpenConnection::new(counters);
+        let ServerConfig {
+            keep_alive,
+            limits,
+            parse_mode,
+            timeouts,
+            ..
+        } = *config;
+        // A single buffered reader is kept for the whole connection, so pipelined requests read ahead together with the previous one aren't lost.
+        let read_timeout = Rc::new(Cell::new(ReadTimeout::default()));
+        let reader: SharedReader = match stream.try_clone() {
+            Ok(read_stream) => {
+                Body::shared_reader(TimedStream::new(read_stream, read_timeout.clone()))
+            }
+            Err(e) => {
+                error!("Stream Error: {:#?}", e);
+                return;
+            }
+        };
+        if stream.set_write_timeout(Some(timeouts.write)).is_err() {
+            return;
+        }
+
+        let mut writer = BufWriter::new(&stream);
+        let mut served = 0;
+        loop {
+            // Wait for the next request. The client closing the connection or staying idle for too long ends it. The first request counts against the header deadline from the moment the connection was accepted.
+            let header_timeout = |start: Instant| {
+                ReadTimeout::new(timeouts.header_read, Some(start + timeouts.header_total))
+            };
+            let wait_start = Instant::now();
+            let wait_deadline = if served == 0 {
+                wait_start + timeouts.header_read.min(timeouts.header_total)
+            } else {
+                wait_start + keep_alive.idle_timeout
+            };
+            // The wait is split into short reads, so idle connections are closed soon after the server starts stopping.
+            read_timeout.set(ReadTimeout::new(STOP_POLL_INTERVAL, Some(wait_deadline)));
+            loop {
+                match reader.borrow_mut().fill_buf() {
+                    Ok(buffer) if !buffer.is_empty() => break,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) && Instant::now() < wait_deadline
+                            && !stopping.load(Ordering::SeqCst) =>
+                    {
+                        continue
+                    }
+                    _ => return,
+                }
+            }
+            // The whole head has to arrive before the deadline, however slowly it trickles in.
+            read_timeout.set(header_timeout(if served == 0 {
+                wait_start
+            } else {
+                Instant::now()
+            }));
+
+            let mut request = match Request::read_request_with(&reader, &limits, parse_mode) {
+                Ok(request) => request,
+                Err(e) => {
+                    warn!("Request Error: {}", e);
+                    if let Some(mut response) = e.to_response() {
+                        if response.send(&mut writer).is_ok() && writer.flush().is_ok() {
+                            Self::linger(&stream, stopping);
+                        }
+                    }
+                    return;
+                }
+            };
+            served += 1;
+            read_timeout.set(ReadTimeout::new(timeouts.body_read, None));
+
+            // 100 Continue is only sent once the route reads the body, so requests rejected by routing or middleware get their final status without the client sending the body.
+            if request.expects_continue() && !request.body.is_empty() {
+                match stream.try_clone() {
+                    // Responses to earlier pipelined requests have to go out before 100 Continue.
+                    Ok(continue_stream) if writer.flush().is_ok() => {
+                        request.body.expect_continue(Box::new(continue_stream))
+                    }
+                    _ => return,
+                }
+            }
+
+            // A panicking route gets a 500 response instead of no response at all. Nothing is sent before the route returns, so the status can always be replaced.
+            let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+                Self::handle_loop(routers, &mut request)
+            }));
+            let mut response = match handled {
+                Ok(response) => response,
+                Err(payload) => {
+                    counters.route_panics.fetch_add(1, Ordering::Relaxed);
+                    error!(
+                        "Route Panic: {} {}: {}",
+                        request.method.as_str(),
+                        request.path,
+                        panic_message(&*payload)
+                    );
+                    // The route may have left the body half read, so the connection can't be reused.
+                    let mut response = Response::new();
+                    response.set_status(500, "Internal Server Error");
+                    response.set_header("Connection", "close");
+                    response
+                }
+            };
+            // The next request starts after the end of this body, so whatever the route didn't read has to be skipped. That is done before the response, so a connection is only advertised as persistent once it can really be reused.
+            let keep_open = keep_alive.enabled
+                && !stopping.load(Ordering::SeqCst)
+                && served < keep_alive.max_requests
+                && request.wants_keep_alive()
+                && !request.body.awaiting_continue()
+                && !response
+                    .header("Connection")
+                    .is_some_and(|value| value.eq_ignore_ascii_case("close"))
+                && request.body.drain(MAX_DRAIN_SIZE);
+            if keep_open {
+                response.set_header("Connection", "keep-alive");
+                if request.version == Version::Http10 {
+                    response.set_header(
+                        "Keep-Alive",
+                        &format!(
+                            "timeout={}, max={}",
+                            keep_alive.idle_timeout.as_secs(),
+                            keep_alive.max_requests - served
+                        ),
+                    );
+                }
+            } else {
+                response.set_header("Connection", "close");
+            }
+
+            if let Err(e) = response.send(&mut writer) {
+                warn!("Response Error: {:#?}", e);
+                return;
+            }
+            if !keep_open {
+                // Unread body or pipelined requests left on a closed connection would reset it and could lose the response.
+                let unread = !request.body.is_complete() || !reader.borrow().buffer().is_empty();
+                if writer.flush().is_ok() && unread {
+                    Self::linger(&stream, stopping);
+                }
+                return;
+            }
+            // Responses to pipelined requests are written in order and sent together once no more requests are waiting in the buffer.
+            if reader.borrow().buffer().is_empty() && writer.flush().is_err() {
+                return;
+            }
+        }
+    }
+
+    // Close the connection after an error response without discarding it. Closing a socket with unread input resets the connection, which can make the client lose the response. Input is discarded for LINGER_TIMEOUT at most, however slowly it trickles in, and not at all once the server is stopping.
+    fn linger(stream: &TcpStream, stopping: &AtomicBool) {
+        if stream.shutdown(Shutdown::Write).is_err() {
+            return;
+        }
+        let deadline = Instant::now() + LINGER_TIMEOUT;
+        let mut buf = [0u8; 8 * 1024];
+        let mut discarded = 0;
+        while discarded < MAX_DRAIN_SIZE && !stopping.load(Ordering::SeqCst) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero()
+                || stream
+                    .set_read_timeout(Some(left.min(STOP_POLL_INTERVAL)))
+                    .is_err()
+            {
+                return;
+            }
+            match (&*stream).read(&mut buf) {
+                Ok(0) => return,
+                Ok(read) => discarded += read,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(_) => return,
+            }
+        }
+    }
+
     // Execute main request-response "loop" logic for the server.
-    fn handle_loop(routers: &Arc<Mutex<Trie<Router>>>, stream: &TcpStream, request: &mut Request) {
+    fn handle_loop(routers: &Arc<Mutex<Trie<Router>>>, request: &mut Request) -> Response {
         Self::check_static_request(request);
         let mut response = Response::new();
         Self::match_router(routers, request, &mut response);
-        log!("Request: {:#?}", request);
-        if let Err(e) = response.send(stream) {
-            log!("Response Error: {:#?}", e);
-        }
+        debug!("Request: {:#?}", request);
+        response
     }
 
     // Check if the request is for a static file, and add the static request data to the request object if so. Also change to forward to the static route.
@@ -118,11 +847,8 @@ impl Server {
     fn is_static_path(request: &Request) -> (bool, String) {
         let mut is_static = false;
         let path = Path::new(&request.path);
-        match path.extension() {
-            Some(extension) => {
-                is_static = is_static_file(&extension.to_string_lossy());
-            }
-            None => {}
+        if let Some(extension) = path.extension() {
+            is_static = is_static_file(&extension.to_string_lossy());
         }
         (is_static, path.to_string_lossy().to_string())
     }
@@ -133,13 +859,38 @@ impl Server {
         request: &mut Request,
         response: &mut Response,
     ) {
-        routers.lock().unwrap().search(&request.path).map(|router| {
+        // The lock is released before running the route, so a panicking route can't poison it.
+        let router = routers.lock().unwrap().search(&request.path);
+        if let Some(router) = router {
             router.execute_middleware(request);
-            router
+            if let Some(func) = router
                 .find_route(request)
                 .and_then(|route| route.method_map.get(&request.method).cloned())
-                .map(|func| (func)(request, response));
-        });
+            {
+                (func)(request, response);
+            }
+            router.execute_after_middleware(request, response);
+        }
+    }
+
+    // Configure persistent connections.
+    pub fn set_keep_alive(&mut self, keep_alive: KeepAlive) {
+        self.config.keep_alive = keep_alive;
+    }
+
+    // Set the limits on request sizes. Requests over a limit are answered with an error status.
+    pub fn set_limits(&mut self, limits: RequestLimits) {
+        self.config.limits = limits;
+    }
+
+    // Set how strictly requests are parsed. Strict mode, the default, should be kept when the server is behind a proxy or load balancer.
+    pub fn set_parse_mode(&mut self, parse_mode: ParseMode) {
+        self.config.parse_mode = parse_mode;
+    }
+
+    // Configure the read and write timeouts of connections. The keep-alive idle timeout is part of the keep-alive settings.
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.config.timeouts = timeouts;
     }
 
     // Register a router with the server. Routers are used to group routes together.
@@ -150,9 +901,9 @@ impl Server {
             .insert(router.base_path.clone().as_str(), router);
     }
 
-    // Register a route with the server that serves static files from a directory starting from project root.
+    // Register a route with the server that serves static files from a directory under the static root.
     pub fn serve_static(&mut self, dir: &str) {
-        let root_path = format!("{}\\{}", self.root_path, dir);
+        let root_path = self.config.static_root.join(dir);
         let mut router = Router::new("/static");
         router.route("", "GET", move |request, response| {
             if let Some((path, extension)) = Self::get_static_file_details(request, &root_path) {
@@ -162,29 +913,42 @@ impl Server {
                     }
                     Err(e) => {
                         response.set_status(404, "Not Found");
-                        log!("File Read Error: {:#?}", e);
+                        warn!("File Read Error: {:#?}", e);
                     }
                 }
+            } else if request.static_request_data.is_some() {
+                response.set_status(404, "Not Found");
             }
         });
         self.router(router);
     }
 
-    fn get_static_file_details(request: &Request, root_path: &str) -> Option<(String, String)> {
+    fn get_static_file_details(request: &Request, root_path: &Path) -> Option<(PathBuf, String)> {
         if let Some(ref data) = request.static_request_data {
             // If the request has a path, use that path to get the file. Otherwise, get the first HTML file in the directory.
             if let Some(ref path) = data.path {
+                // Only plain names are joined, so the path can't leave the root.
+                let relative = Path::new(path.trim_start_matches('/'));
+                if !relative
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+                {
+                    return None;
+                }
                 return Some((
-                    format!("{}\\{}", root_path, path),
-                    path.split('.').last().unwrap_or("text/plain").to_string(),
+                    root_path.join(relative),
+                    path.split('.')
+                        .next_back()
+                        .unwrap_or("text/plain")
+                        .to_string(),
                 ));
             } else {
-                match get_first_html_file_name(Path::new(&root_path)) {
+                match get_first_html_file_name(root_path) {
                     Ok((resource, extension)) => {
-                        return Some((format!("{}\\{}", root_path, resource), extension));
+                        return Some((root_path.join(resource), extension));
                     }
                     Err(e) => {
-                        log!("Static File Retrieval Error (No HTML File Found): {:#?}", e);
+                        warn!("Static File Retrieval Error (No HTML File Found): {:#?}", e);
                         return None;
                     }
                 }
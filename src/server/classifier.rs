@@ -0,0 +1,121 @@
+use std::io;
+use std::mem;
+use std::net::TcpStream;
+use std::str;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::{Dispatcher, ServerConfig};
+use crate::communication::protocol::{parse_request_line, RequestTarget};
+use crate::communication::router::Router;
+use crate::ds::trie::Trie;
+use crate::utils::thread_pool::Priority;
+
+// How much of a new connection's input is looked at for the request line to pick its priority lane.
+const PRIORITY_PEEK_SIZE: usize = 2048;
+
+// How long the classifier waits for the request line of a new connection. Connections that take longer go in the normal lane.
+const CLASSIFY_WAIT: Duration = Duration::from_millis(100);
+
+// How often connections waiting for their request line are looked at again.
+const CLASSIFY_POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+// Thread that holds new connections until their request line arrives, then hands them to the lane of their route. Workers are only given connections that are ready, so none waits on a client slow to start.
+pub(super) struct Classifier {
+    sender: SyncSender<(TcpStream, Instant)>,
+    thread: JoinHandle<()>,
+}
+
+impl Classifier {
+    pub(super) fn start(dispatcher: Dispatcher) -> io::Result<Self> {
+        // The acceptor waits while this many connections are waiting to be classified, as it does while the queue of the pool is full.
+        let (sender, receiver) = mpsc::sync_channel(dispatcher.config.pool.queue_capacity);
+        let thread = thread::Builder::new()
+            .name(String::from("tiny-http-classifier"))
+            .spawn(move || Self::run(&dispatcher, receiver))?;
+        Ok(Self { sender, thread })
+    }
+
+    // Hand a connection just accepted to the classifier.
+    pub(super) fn classify(&self, stream: TcpStream) {
+        // The classifier only stops once finish drops the sender, so this can't fail.
+        let _ = self.sender.send((stream, Instant::now()));
+    }
+
+    // Hand the connections still waiting for their request line to the normal lane and stop the classifier.
+    pub(super) fn finish(self) {
+        drop(self.sender);
+        let _ = self.thread.join();
+    }
+
+    // Body of the classifier thread.
+    fn run(dispatcher: &Dispatcher, receiver: Receiver<(TcpStream, Instant)>) {
+        let mut waiting: Vec<(TcpStream, Instant)> = Vec::new();
+        let mut open = true;
+        while open || !waiting.is_empty() {
+            let received = if waiting.is_empty() {
+                receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                receiver.recv_timeout(CLASSIFY_POLL_INTERVAL)
+            };
+            match received {
+                // Looking at the input without waiting lets one thread watch all the connections.
+                Ok((stream, accepted)) => match stream.set_nonblocking(true) {
+                    Ok(()) => waiting.push((stream, accepted)),
+                    Err(e) => error!("Stream Error: {:#?}", e),
+                },
+                Err(RecvTimeoutError::Disconnected) => open = false,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            for (stream, accepted) in mem::take(&mut waiting) {
+                let priority =
+                    match connection_priority(&dispatcher.routers, &stream, &dispatcher.config) {
+                        Some(priority) => priority,
+                        None if !open || accepted.elapsed() >= CLASSIFY_WAIT => Priority::Normal,
+                        None => {
+                            waiting.push((stream, accepted));
+                            continue;
+                        }
+                    };
+                match stream.set_nonblocking(false) {
+                    Ok(()) => dispatcher.dispatch(stream, priority),
+                    Err(e) => error!("Stream Error: {:#?}", e),
+                }
+            }
+        }
+    }
+}
+
+// Pick the priority lane of a connection from the route of its first request, or None if the request line hasn't fully arrived yet. Later requests on the connection are served by the same worker, in the same lane. The stream must be non-blocking.
+fn connection_priority(
+    routers: &Mutex<Trie<Router>>,
+    stream: &TcpStream,
+    config: &ServerConfig,
+) -> Option<Priority> {
+    let mut buffer = [0; PRIORITY_PEEK_SIZE];
+    let peeked = match stream.peek(&mut buffer) {
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => return None,
+        Ok(peeked) if peeked > 0 => peeked,
+        // The client closed the connection or it failed, which serving it finds out.
+        _ => return Some(Priority::Normal),
+    };
+    let Some(end) = buffer[..peeked].iter().position(|&byte| byte == b'\n') else {
+        // A request line that doesn't fit in the buffer won't be seen however long the classifier waits.
+        return (peeked == buffer.len()).then_some(Priority::Normal);
+    };
+    let Ok(line) = str::from_utf8(&buffer[..end]) else {
+        return Some(Priority::Normal);
+    };
+    let (method, path) = match parse_request_line(line.trim_end_matches('\r'), &config.limits) {
+        Ok((method, RequestTarget::Origin { path, .. }, _))
+        | Ok((method, RequestTarget::Absolute { path, .. }, _)) => (method, path),
+        // The error is answered once the connection is served.
+        _ => return Some(Priority::Normal),
+    };
+    let router = routers.lock().unwrap().search(&path);
+    Some(router.map_or(Priority::Normal, |router| {
+        router.priority_for(&path, &method)
+    }))
+}
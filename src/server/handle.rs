@@ -0,0 +1,122 @@
+use std::error::Error;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use super::{Server, ServerStats, StatsSource, STOP_POLL_INTERVAL};
+
+// How long connecting to the server to wake up its accept loop may take.
+const WAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Handle to a server running on a background thread, returned by Server::spawn.
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    stopping: Arc<AtomicBool>,
+    stats: StatsSource,
+    thread: JoinHandle<(Server, io::Result<()>)>,
+    // Disconnected or sent to once the accept loop has returned.
+    finished: Receiver<()>,
+}
+
+impl ServerHandle {
+    pub(super) fn new(
+        local_addr: SocketAddr,
+        stopping: Arc<AtomicBool>,
+        stats: StatsSource,
+        thread: JoinHandle<(Server, io::Result<()>)>,
+        finished: Receiver<()>,
+    ) -> Self {
+        Self {
+            local_addr,
+            stopping,
+            stats,
+            thread,
+            finished,
+        }
+    }
+
+    // The address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    // Snapshot of the server's activity, as Server::stats.
+    pub fn stats(&self) -> ServerStats {
+        self.stats.stats()
+    }
+
+    // Stop accepting connections. Requests in progress are finished in the background, join waits for them. Fails if the accept loop couldn't be woken up to notice, in which case it keeps waiting for a connection.
+    pub fn shutdown(&self) -> io::Result<()> {
+        stop(&self.stopping, self.local_addr)
+    }
+
+    // Stop accepting connections and wait up to the timeout for requests in progress. Connections and background jobs still running when it passes are left to finish on their own and a TimedOut error is returned. If the accept loop couldn't be woken up, its error is returned without waiting.
+    pub fn shutdown_timeout(self, timeout: Duration) -> Result<(), Box<dyn Error>> {
+        self.shutdown()?;
+        self.finish(Some(timeout))
+    }
+
+    // Wait until the server has stopped, then up to its shutdown timeout for the connections and background jobs to finish.
+    pub fn join(self) -> Result<(), Box<dyn Error>> {
+        self.finish(None)
+    }
+
+    fn finish(self, timeout: Option<Duration>) -> Result<(), Box<dyn Error>> {
+        // The accept loop may still be blocked if waking it up failed when the server was stopped, so it is woken again once, and given up on if that fails too.
+        let mut stopped_at = None;
+        while let Err(RecvTimeoutError::Timeout) = self.finished.recv_timeout(STOP_POLL_INTERVAL) {
+            if !self.stopping.load(Ordering::SeqCst) {
+                continue;
+            }
+            if stopped_at.get_or_insert_with(Instant::now).elapsed() >= WAKE_TIMEOUT {
+                wake(self.local_addr)?;
+                break;
+            }
+        }
+        let (mut server, result) = self
+            .thread
+            .join()
+            .map_err(|_| io::Error::other("Server thread panicked"))?;
+        let timeout = timeout.unwrap_or(server.config.shutdown_timeout);
+        let finished = server.finish_shutdown(Some(timeout));
+        result?;
+        if !finished {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Connections or background jobs were still running after the shutdown timeout",
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Tell the accept loop of a server to stop. The loop is blocked waiting for a connection, so one is made to wake it up. An error means the loop may not notice until another connection arrives.
+pub(super) fn stop(stopping: &AtomicBool, local_addr: SocketAddr) -> io::Result<()> {
+    if stopping.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    wake(local_addr)
+}
+
+// Connect to the server to wake up its accept loop.
+fn wake(local_addr: SocketAddr) -> io::Result<()> {
+    let mut address = local_addr;
+    // A server listening on all interfaces is reached through loopback.
+    if address.ip().is_unspecified() {
+        address.set_ip(match address.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+    match TcpStream::connect_timeout(&address, WAKE_TIMEOUT) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Wake Error: could not connect to {}: {:#?}", address, e);
+            Err(e)
+        }
+    }
+}
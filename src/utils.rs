@@ -1,5 +1,12 @@
+pub mod base64;
+pub mod crypto;
+pub mod date;
+pub mod executor;
 pub mod file;
 pub mod general;
 pub mod guess;
+pub mod json;
+pub mod signal;
 pub mod stream;
 pub mod thread_pool;
+pub mod url;
@@ -0,0 +1,69 @@
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// Encode bytes as unpadded URL-safe base64, which is valid inside cookie values.
+pub fn encode_url_safe(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..=chunk.len() {
+            encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+// Decode unpadded URL-safe base64. Returns None on invalid input, including padding and unused bits that aren't zero, so every byte string has exactly one valid encoding.
+pub fn decode_url_safe(data: &str) -> Option<Vec<u8>> {
+    let data = data.as_bytes();
+    if data.len() % 4 == 1 {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(data.len() * 3 / 4);
+    for chunk in data.chunks(4) {
+        let mut n = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let value = ALPHABET.iter().position(|a| a == c)? as u32;
+            n |= value << (18 - 6 * i);
+        }
+        // The bits of the last character below the last decoded byte.
+        let unused = (1u32 << (24 - 8 * (chunk.len() - 1))) - 1;
+        if n & unused != 0 {
+            return None;
+        }
+        for i in 0..chunk.len() - 1 {
+            decoded.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for len in 0..8 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37 + 200) as u8).collect();
+            let encoded = encode_url_safe(&data);
+            assert!(!encoded.contains('='));
+            assert_eq!(decode_url_safe(&encoded), Some(data));
+        }
+    }
+
+    #[test]
+    fn non_canonical_encodings_are_rejected() {
+        assert_eq!(encode_url_safe(b"a"), "YQ");
+        assert_eq!(decode_url_safe("YR"), None);
+        assert_eq!(encode_url_safe(b"ab"), "YWI");
+        assert_eq!(decode_url_safe("YWJ"), None);
+        assert_eq!(decode_url_safe("YQ=="), None);
+        assert_eq!(decode_url_safe("Y"), None);
+        assert_eq!(decode_url_safe("Y+Q"), None);
+    }
+}
@@ -0,0 +1,480 @@
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::general::panic_message;
+use super::thread_pool::{PoolConfig, Priority, Spawner, ThreadPool};
+
+// Runs background jobs, now or on a schedule, on a thread pool of its own so they can't hold up request workers. Clones share the same pool, which runs until shutdown is called or the last clone is dropped.
+#[derive(Clone)]
+pub struct Executor {
+    shared: Arc<Shared>,
+    // Dropped with the last clone, stopping the timer thread, which would otherwise keep the shared state alive.
+    _timers: Arc<TimersGuard>,
+}
+
+// Handle to a job queued on an executor. Cancelling it stops the job from running if it hasn't started, and stops further runs of a periodic job. Jobs the executor drops without running, because it was shut down, are cancelled too.
+#[derive(Debug, Clone)]
+pub struct TaskHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+struct Shared {
+    // Taken out on shutdown.
+    pool: Mutex<Option<ThreadPool>>,
+    // Jobs are queued through this rather than the pool, so a job waiting for room in a full queue holds no lock that shutdown or other jobs need.
+    spawner: Spawner,
+    timers: Mutex<Timers>,
+    // Signalled when a timer is added or the executor shuts down.
+    changed: Condvar,
+    closed: AtomicBool,
+}
+
+struct TimersGuard(Arc<Shared>);
+
+struct Timers {
+    queue: BinaryHeap<Timer>,
+    // Orders timers due at the same instant by when they were added.
+    next_seq: u64,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+// A job waiting for its time to run.
+struct Timer {
+    due: Instant,
+    seq: u64,
+    task: Task,
+}
+
+enum Task {
+    Once(Box<dyn FnOnce() + Send + 'static>, TaskHandle),
+    Every(Arc<dyn Fn() + Send + Sync + 'static>, Duration, TaskHandle),
+}
+
+impl TaskHandle {
+    fn new() -> Self {
+        Self {
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl Executor {
+    pub fn new(config: PoolConfig) -> Self {
+        let pool = ThreadPool::with_config(config);
+        let shared = Arc::new(Shared {
+            spawner: pool.spawner(),
+            pool: Mutex::new(Some(pool)),
+            timers: Mutex::new(Timers {
+                queue: BinaryHeap::new(),
+                next_seq: 0,
+                thread: None,
+            }),
+            changed: Condvar::new(),
+            closed: AtomicBool::new(false),
+        });
+        Self {
+            _timers: Arc::new(TimersGuard(Arc::clone(&shared))),
+            shared,
+        }
+    }
+
+    // Run a job as soon as a background worker is free.
+    pub fn spawn<F>(&self, f: F) -> TaskHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let handle = TaskHandle::new();
+        if !self.shared.run(Task::Once(Box::new(f), handle.clone())) {
+            handle.cancel();
+        }
+        handle
+    }
+
+    // Run a job once the delay has passed.
+    pub fn spawn_after<F>(&self, delay: Duration, f: F) -> TaskHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let handle = TaskHandle::new();
+        self.schedule(delay, Task::Once(Box::new(f), handle.clone()));
+        handle
+    }
+
+    // Run a job repeatedly, waiting the interval before the first run and between the end of a run and the start of the next, so runs never overlap. Panics if the interval is zero.
+    pub fn every<F>(&self, interval: Duration, f: F) -> TaskHandle
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        assert!(
+            !interval.is_zero(),
+            "Interval of a periodic job must be greater than 0."
+        );
+        let handle = TaskHandle::new();
+        self.schedule(interval, Task::Every(Arc::new(f), interval, handle.clone()));
+        handle
+    }
+
+    fn schedule(&self, delay: Duration, task: Task) {
+        let mut timers = self.shared.timers.lock().unwrap();
+        if self.shared.closed.load(Ordering::SeqCst) {
+            task.handle().cancel();
+            return;
+        }
+        // The timer thread is started with the first scheduled job, so executors that only spawn don't need it.
+        if timers.thread.is_none() {
+            let shared = Arc::clone(&self.shared);
+            match thread::Builder::new()
+                .name(String::from("tiny-http-timer"))
+                .spawn(move || shared.run_timers())
+            {
+                Ok(thread) => timers.thread = Some(thread),
+                Err(e) => error!("Could not start the timer thread: {}", e),
+            }
+        }
+        Shared::add_timer(&mut timers, Instant::now() + delay, task);
+        self.shared.changed.notify_one();
+    }
+
+    // Stop running scheduled jobs and wait for jobs already running or queued. Jobs still waiting for their time are dropped, and jobs added afterwards never run. With a timeout, returns false if jobs were still running when it passed.
+    pub fn shutdown(&self, timeout: Option<Duration>) -> bool {
+        if let Some(thread) = self.shared.stop_timers() {
+            let _ = thread.join();
+        }
+        let pool = self.shared.pool.lock().unwrap().take();
+        pool.is_none_or(|mut pool| pool.shutdown(timeout))
+    }
+}
+
+// Jobs queued or running when the last clone is dropped still finish, as the pool waits for them when it is dropped in turn.
+impl Drop for TimersGuard {
+    fn drop(&mut self) {
+        // The timer thread isn't waited for, as the executor may be dropped by one of its own jobs, which the timer thread could be waiting to queue another job behind.
+        self.0.stop_timers();
+    }
+}
+
+impl Shared {
+    // Close the executor and drop the timers still waiting, returning the timer thread, which then stops.
+    fn stop_timers(&self) -> Option<thread::JoinHandle<()>> {
+        self.closed.store(true, Ordering::SeqCst);
+        let (dropped, thread) = {
+            let mut timers = self.timers.lock().unwrap();
+            self.changed.notify_all();
+            (mem::take(&mut timers.queue), timers.thread.take())
+        };
+        // Jobs may own clones of the executor, so they are dropped without the lock held.
+        for timer in dropped {
+            timer.task.handle().cancel();
+        }
+        thread
+    }
+
+    // Queue a job on the pool, waiting for room if its queue is full. Returns false if the executor is shut down, and the job is dropped like the timers still waiting.
+    fn run(self: &Arc<Self>, task: Task) -> bool {
+        let shared = Arc::clone(self);
+        self.spawner
+            .execute_with(Priority::Normal, move || match task {
+                Task::Once(f, handle) => {
+                    if !handle.is_cancelled() {
+                        f();
+                    }
+                }
+                Task::Every(f, interval, handle) => {
+                    if handle.is_cancelled() {
+                        return;
+                    }
+                    // A run that panics must not end the schedule, so the panic is caught here rather than by the pool.
+                    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f())) {
+                        error!("Periodic job panicked: {}", panic_message(&*payload));
+                    }
+                    // Schedule the next run only now, so a slow run pushes the next one back instead of overlapping it.
+                    if !handle.is_cancelled() && !shared.closed.load(Ordering::SeqCst) {
+                        let mut timers = shared.timers.lock().unwrap();
+                        Self::add_timer(
+                            &mut timers,
+                            Instant::now() + interval,
+                            Task::Every(f, interval, handle),
+                        );
+                        shared.changed.notify_one();
+                    }
+                }
+            })
+            .is_ok()
+    }
+
+    fn add_timer(timers: &mut Timers, due: Instant, task: Task) {
+        let seq = timers.next_seq;
+        timers.next_seq += 1;
+        timers.queue.push(Timer { due, seq, task });
+    }
+
+    // Body of the timer thread: hand jobs to the pool as they come due.
+    fn run_timers(self: Arc<Self>) {
+        let mut timers = self.timers.lock().unwrap();
+        while !self.closed.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            match timers.queue.peek() {
+                Some(timer) if timer.due <= now => {
+                    let timer = timers.queue.pop().unwrap();
+                    // The pool may block while its queue is full, so timers can be added meanwhile.
+                    drop(timers);
+                    let handle = timer.task.handle().clone();
+                    if !handle.is_cancelled() && !self.run(timer.task) {
+                        // Dropped by the pool, which was shut down after the timer came due.
+                        handle.cancel();
+                    }
+                    timers = self.timers.lock().unwrap();
+                }
+                Some(timer) => {
+                    let wait = timer.due - now;
+                    timers = self.changed.wait_timeout(timers, wait).unwrap().0;
+                }
+                None => timers = self.changed.wait(timers).unwrap(),
+            }
+        }
+    }
+}
+
+impl Task {
+    fn handle(&self) -> &TaskHandle {
+        match self {
+            Task::Once(_, handle) | Task::Every(_, _, handle) => handle,
+        }
+    }
+}
+
+// The heap is a max-heap, so timers compare in reverse to pop the earliest first.
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        other
+            .due
+            .cmp(&self.due)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Timer {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc;
+
+    fn executor() -> Executor {
+        Executor::new(PoolConfig::fixed(2))
+    }
+
+    #[test]
+    fn spawn_after_waits_for_the_delay() {
+        let executor = executor();
+        let (done, finished) = mpsc::channel();
+        let start = Instant::now();
+        executor.spawn_after(Duration::from_millis(50), move || {
+            done.send(Instant::now()).unwrap()
+        });
+        let ran_at = finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(ran_at - start >= Duration::from_millis(50));
+        assert!(executor.shutdown(Some(Duration::from_secs(5))));
+    }
+
+    #[test]
+    fn timers_run_in_order_of_their_due_time() {
+        let executor = executor();
+        let (done, finished) = mpsc::channel();
+        for (delay, name) in [(60, "third"), (20, "first"), (40, "second")] {
+            let done = done.clone();
+            executor.spawn_after(Duration::from_millis(delay), move || {
+                done.send(name).unwrap()
+            });
+        }
+        let order: Vec<_> = (0..3)
+            .map(|_| finished.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        assert_eq!(order, ["first", "second", "third"]);
+        executor.shutdown(None);
+    }
+
+    #[test]
+    fn every_repeats_until_cancelled() {
+        let executor = executor();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counted = runs.clone();
+        let handle = executor.every(Duration::from_millis(10), move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+        });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while runs.load(Ordering::SeqCst) < 3 {
+            assert!(Instant::now() < deadline, "the job didn't repeat");
+            thread::sleep(Duration::from_millis(5));
+        }
+        handle.cancel();
+        // A run already queued when the job was cancelled may still finish.
+        thread::sleep(Duration::from_millis(30));
+        let after_cancel = runs.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(runs.load(Ordering::SeqCst), after_cancel);
+        executor.shutdown(None);
+    }
+
+    #[test]
+    fn panicking_runs_keep_the_schedule() {
+        let executor = executor();
+        let (done, finished) = mpsc::channel();
+        let done = Mutex::new(done);
+        let runs = AtomicUsize::new(0);
+        executor.every(Duration::from_millis(10), move || {
+            if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first run");
+            }
+            let _ = done.lock().unwrap().send(());
+        });
+        finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        executor.shutdown(None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Interval of a periodic job must be greater than 0.")]
+    fn every_rejects_a_zero_interval() {
+        executor().every(Duration::ZERO, || {});
+    }
+
+    #[test]
+    fn cancelled_jobs_do_not_run() {
+        let executor = executor();
+        let ran = Arc::new(AtomicBool::new(false));
+        let flag = ran.clone();
+        let handle = executor.spawn_after(Duration::from_millis(20), move || {
+            flag.store(true, Ordering::SeqCst)
+        });
+        handle.cancel();
+        assert!(handle.is_cancelled());
+        thread::sleep(Duration::from_millis(60));
+        assert!(executor.shutdown(Some(Duration::from_secs(5))));
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn shutdown_runs_queued_jobs_and_drops_scheduled_ones() {
+        let executor = Executor::new(PoolConfig::fixed(1));
+        let ran = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            let ran = ran.clone();
+            executor.spawn(move || {
+                thread::sleep(Duration::from_millis(2));
+                ran.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        let scheduled = Arc::new(AtomicBool::new(false));
+        let flag = scheduled.clone();
+        let dropped = executor.spawn_after(Duration::from_millis(200), move || {
+            flag.store(true, Ordering::SeqCst)
+        });
+        assert!(executor.shutdown(Some(Duration::from_secs(5))));
+        assert_eq!(ran.load(Ordering::SeqCst), 10);
+        assert!(dropped.is_cancelled());
+
+        // Jobs queued after shutdown are dropped, and their handles say so.
+        let flag = scheduled.clone();
+        assert!(executor
+            .spawn(move || flag.store(true, Ordering::SeqCst))
+            .is_cancelled());
+        let flag = scheduled.clone();
+        assert!(executor
+            .spawn_after(Duration::ZERO, move || flag.store(true, Ordering::SeqCst))
+            .is_cancelled());
+        let flag = scheduled.clone();
+        assert!(executor
+            .every(Duration::from_millis(1), move || flag
+                .store(true, Ordering::SeqCst))
+            .is_cancelled());
+        thread::sleep(Duration::from_millis(250));
+        assert!(!scheduled.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn dropping_the_last_clone_stops_the_timer_thread() {
+        let executor = executor();
+        let (done, finished) = mpsc::channel();
+        let done = Mutex::new(done);
+        let handle = executor.every(Duration::from_millis(10), move || {
+            let _ = done.lock().unwrap().send(());
+        });
+        finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        let shared = Arc::downgrade(&executor.shared);
+        let clone = executor.clone();
+        drop(executor);
+        assert!(!handle.is_cancelled());
+        drop(clone);
+
+        // The timer thread and the pool let go of the state once they have stopped.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while shared.strong_count() > 0 {
+            assert!(Instant::now() < deadline, "the executor was not freed");
+            thread::sleep(Duration::from_millis(5));
+        }
+        while finished.try_recv().is_ok() {}
+        thread::sleep(Duration::from_millis(50));
+        assert!(finished.try_recv().is_err());
+    }
+
+    #[test]
+    fn shutdown_is_not_held_up_by_a_job_waiting_for_room() {
+        let executor = Executor::new(PoolConfig {
+            queue_capacity: 1,
+            ..PoolConfig::fixed(2)
+        });
+        let (release, released) = mpsc::channel::<()>();
+        let (started, has_started) = mpsc::channel();
+        executor.spawn(move || {
+            started.send(()).unwrap();
+            let _ = released.recv();
+        });
+        has_started.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // The second job fills the queue with a job the busy worker can't take, then waits for room to queue another.
+        let (blocker_release, blocker_released) = mpsc::channel::<()>();
+        let (queuing, is_queuing) = mpsc::channel();
+        let spawner = executor.clone();
+        executor.spawn(move || {
+            spawner.spawn(move || {
+                let _ = blocker_released.recv();
+            });
+            queuing.send(()).unwrap();
+            spawner.spawn(|| {});
+        });
+        is_queuing.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let start = Instant::now();
+        assert!(!executor.shutdown(Some(Duration::from_millis(100))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        release.send(()).unwrap();
+        blocker_release.send(()).unwrap();
+    }
+}
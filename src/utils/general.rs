@@ -1,6 +1,19 @@
+use std::any::Any;
+
 pub fn is_static_file(file_extension: &str) -> bool {
-    match file_extension.to_lowercase().as_str() {
-        "html" | "css" | "js" | "png" | "jpg" | "jpeg" | "gif" | "ico" => true,
-        _ => false,
+    matches!(
+        file_extension.to_lowercase().as_str(),
+        "html" | "css" | "js" | "png" | "jpg" | "jpeg" | "gif" | "ico"
+    )
+}
+
+// Get the message of a caught panic. Panics with a formatted message carry a String, others a &str.
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Unknown panic payload"
     }
 }
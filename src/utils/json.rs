@@ -0,0 +1,845 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter, Write};
+
+// Maximum nesting of arrays and objects, so hostile input can't overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+// Largest integer an f64 holds exactly, 2^53.
+const MAX_SAFE_FLOAT_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+// A JSON value (RFC 8259).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum JsonValue {
+    #[default]
+    Null,
+    Bool(bool),
+    Number(JsonNumber),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(BTreeMap<String, JsonValue>),
+}
+
+// A JSON number. Integers are kept exactly, so 64-bit IDs survive a round trip, other numbers are stored as f64. Numbers compare by value, so 1 equals 1.0.
+#[derive(Debug, Clone, Copy)]
+pub enum JsonNumber {
+    Integer(i128),
+    Float(f64),
+}
+
+impl JsonNumber {
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            JsonNumber::Integer(n) => n as f64,
+            JsonNumber::Float(n) => n,
+        }
+    }
+
+    // Get the number as an integer. Floats only qualify when they have no fractional part and are small enough to be exact.
+    pub fn as_integer(&self) -> Option<i128> {
+        match *self {
+            JsonNumber::Integer(n) => Some(n),
+            JsonNumber::Float(n) if n.fract() == 0.0 && n.abs() < MAX_SAFE_FLOAT_INTEGER => {
+                Some(n as i128)
+            }
+            JsonNumber::Float(_) => None,
+        }
+    }
+}
+
+impl PartialEq for JsonNumber {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (JsonNumber::Integer(a), JsonNumber::Integer(b)) => a == b,
+            _ => self.as_f64() == other.as_f64(),
+        }
+    }
+}
+
+impl Display for JsonNumber {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            JsonNumber::Integer(n) => write!(f, "{}", n),
+            // Integral floats are written without a fraction, non-finite numbers have no JSON representation.
+            JsonNumber::Float(n) if !n.is_finite() => f.write_str("null"),
+            JsonNumber::Float(n) if n.fract() == 0.0 && n.abs() < 1e15 => {
+                write!(f, "{}", n as i64)
+            }
+            JsonNumber::Float(n) if n.abs() >= 1e15 || n.abs() < 1e-6 => write!(f, "{:e}", n),
+            JsonNumber::Float(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+// Error while parsing JSON, with the position where it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {} column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl Error for JsonError {}
+
+impl JsonValue {
+    pub fn parse(input: &str) -> Result<JsonValue, JsonError> {
+        let mut parser = Parser {
+            input: input.as_bytes(),
+            position: 0,
+        };
+        parser.skip_whitespace();
+        let value = parser.parse_value(0)?;
+        parser.skip_whitespace();
+        if parser.position < parser.input.len() {
+            return Err(parser.error("Trailing characters after JSON value"));
+        }
+        Ok(value)
+    }
+
+    // Get a member of an object. Returns None for other types.
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        self.as_object().and_then(|object| object.get(key))
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, JsonValue::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(n) => Some(n.as_f64()),
+            _ => None,
+        }
+    }
+
+    // Get the number as an integer. Returns None if it has a fractional part or is out of range.
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_integer().and_then(|n| i64::try_from(n).ok())
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.as_integer().and_then(|n| u64::try_from(n).ok())
+    }
+
+    fn as_integer(&self) -> Option<i128> {
+        match self {
+            JsonValue::Number(n) => n.as_integer(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<JsonValue>> {
+        match self {
+            JsonValue::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&BTreeMap<String, JsonValue>> {
+        match self {
+            JsonValue::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+
+    // Serialize with two space indentation.
+    pub fn to_pretty_string(&self) -> String {
+        let mut output = String::new();
+        self.write(&mut output, Some(0)).unwrap();
+        output
+    }
+
+    fn write(&self, f: &mut impl Write, indent: Option<usize>) -> fmt::Result {
+        let newline = |f: &mut dyn Write, level: usize| match indent {
+            Some(_) => write!(f, "\n{:width$}", "", width = level * 2),
+            None => Ok(()),
+        };
+        let level = indent.unwrap_or(0);
+        match self {
+            JsonValue::Null => f.write_str("null"),
+            JsonValue::Bool(b) => write!(f, "{}", b),
+            JsonValue::Number(n) => write!(f, "{}", n),
+            JsonValue::String(s) => write_escaped(f, s),
+            JsonValue::Array(array) if array.is_empty() => f.write_str("[]"),
+            JsonValue::Array(array) => {
+                f.write_char('[')?;
+                for (i, value) in array.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    newline(f, level + 1)?;
+                    value.write(f, indent.map(|level| level + 1))?;
+                }
+                newline(f, level)?;
+                f.write_char(']')
+            }
+            JsonValue::Object(object) if object.is_empty() => f.write_str("{}"),
+            JsonValue::Object(object) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in object.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    newline(f, level + 1)?;
+                    write_escaped(f, key)?;
+                    f.write_str(if indent.is_some() { ": " } else { ":" })?;
+                    value.write(f, indent.map(|level| level + 1))?;
+                }
+                newline(f, level)?;
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+// Serializes compactly, e.g. value.to_string().
+impl Display for JsonValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.write(f, None)
+    }
+}
+
+fn write_escaped(f: &mut impl Write, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> JsonError {
+        let consumed = &self.input[..self.position.min(self.input.len())];
+        let line = consumed.iter().filter(|b| **b == b'\n').count() + 1;
+        let line_start = consumed
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |i| i + 1);
+        JsonError {
+            message: message.to_string(),
+            line,
+            column: self.position - line_start + 1,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn expect_literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue, JsonError> {
+        if self.input[self.position..].starts_with(literal.as_bytes()) {
+            self.position += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("Invalid literal"))
+        }
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<JsonValue, JsonError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("JSON nested too deeply"));
+        }
+        match self.peek() {
+            Some(b'n') => self.expect_literal("null", JsonValue::Null),
+            Some(b't') => self.expect_literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.expect_literal("false", JsonValue::Bool(false)),
+            Some(b'"') => self.parse_string().map(JsonValue::String),
+            Some(b'[') => self.parse_array(depth),
+            Some(b'{') => self.parse_object(depth),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn parse_array(&mut self, depth: usize) -> Result<JsonValue, JsonError> {
+        self.position += 1;
+        let mut array = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(JsonValue::Array(array));
+        }
+        loop {
+            self.skip_whitespace();
+            array.push(self.parse_value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Array(array));
+                }
+                _ => return Err(self.error("Expected ',' or ']' in array")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self, depth: usize) -> Result<JsonValue, JsonError> {
+        self.position += 1;
+        let mut object = BTreeMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(JsonValue::Object(object));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("Expected string key in object"));
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            if self.peek() != Some(b':') {
+                return Err(self.error("Expected ':' after object key"));
+            }
+            self.position += 1;
+            self.skip_whitespace();
+            object.insert(key, self.parse_value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Object(object));
+                }
+                _ => return Err(self.error("Expected ',' or '}' in object")),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, JsonError> {
+        let start = self.position;
+        let digits = |parser: &mut Self| {
+            let digits_start = parser.position;
+            while matches!(parser.peek(), Some(b'0'..=b'9')) {
+                parser.position += 1;
+            }
+            parser.position > digits_start
+        };
+
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+        if self.peek() == Some(b'0') {
+            self.position += 1;
+        } else if !digits(self) {
+            return Err(self.error("Invalid number"));
+        }
+        if self.peek() == Some(b'.') {
+            self.position += 1;
+            if !digits(self) {
+                return Err(self.error("Expected digits after decimal point"));
+            }
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.position += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.position += 1;
+            }
+            if !digits(self) {
+                return Err(self.error("Expected digits in exponent"));
+            }
+        }
+
+        // The number only contains ASCII characters checked above. Integers too large even for i128 fall back to f64.
+        let number = std::str::from_utf8(&self.input[start..self.position])
+            .map_err(|_| self.error("Invalid number"))?;
+        if !number.contains(['.', 'e', 'E']) {
+            if let Ok(n) = number.parse::<i128>() {
+                return Ok(JsonValue::Number(JsonNumber::Integer(n)));
+            }
+        }
+        number
+            .parse::<f64>()
+            .map(|n| JsonValue::Number(JsonNumber::Float(n)))
+            .map_err(|_| self.error("Invalid number"))
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.position += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("Unterminated string")),
+                Some(b'"') => {
+                    self.position += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.parse_unicode_escape()?,
+                        _ => return Err(self.error("Invalid escape sequence")),
+                    };
+                    self.position += 1;
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+                }
+                Some(b) if b < 0x20 => {
+                    return Err(self.error("Control character in string"));
+                }
+                Some(b) => {
+                    bytes.push(b);
+                    self.position += 1;
+                }
+            }
+        }
+        // The input is a &str, and escapes are encoded as UTF-8, so this can't fail.
+        String::from_utf8(bytes).map_err(|_| self.error("Invalid UTF-8 in string"))
+    }
+
+    // Parse the XXXX of a \uXXXX escape, combining surrogate pairs. Leaves the position on the last hex digit.
+    fn parse_unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.parse_hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.input[self.position + 1..].starts_with(b"\\u") {
+                return Err(self.error("Unpaired surrogate in string"));
+            }
+            self.position += 2;
+            let low = self.parse_hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("Invalid low surrogate in string"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("Invalid unicode escape"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+        let hex = self
+            .input
+            .get(self.position + 1..self.position + 5)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| self.error("Invalid unicode escape"))?;
+        self.position += 4;
+        Ok(hex)
+    }
+}
+
+// Types that can be converted to JSON, used with Response::json.
+pub trait ToJson {
+    fn to_json(&self) -> JsonValue;
+}
+
+// Types that can be built from JSON, used with Request::json and the Json extractor.
+pub trait FromJson: Sized {
+    // Build the type from the value, returning a message for the client on failure.
+    fn from_json(value: &JsonValue) -> Result<Self, String>;
+}
+
+impl ToJson for JsonValue {
+    fn to_json(&self) -> JsonValue {
+        self.clone()
+    }
+}
+
+impl FromJson for JsonValue {
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+}
+
+impl ToJson for bool {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Bool(*self)
+    }
+}
+
+impl FromJson for bool {
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        value
+            .as_bool()
+            .ok_or_else(|| String::from("expected a boolean"))
+    }
+}
+
+impl ToJson for str {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::String(self.to_string())
+    }
+}
+
+impl ToJson for String {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::String(self.clone())
+    }
+}
+
+impl FromJson for String {
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        value
+            .as_str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| String::from("expected a string"))
+    }
+}
+
+impl ToJson for f64 {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Number(JsonNumber::Float(*self))
+    }
+}
+
+impl FromJson for f64 {
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        value
+            .as_f64()
+            .ok_or_else(|| String::from("expected a number"))
+    }
+}
+
+macro_rules! impl_json_integer {
+    ($($t:ty),*) => {
+        $(
+            // Every supported integer type fits in an i128, so the value is kept exactly.
+            impl ToJson for $t {
+                fn to_json(&self) -> JsonValue {
+                    JsonValue::Number(JsonNumber::Integer(*self as i128))
+                }
+            }
+
+            impl FromJson for $t {
+                fn from_json(value: &JsonValue) -> Result<Self, String> {
+                    value
+                        .as_integer()
+                        .and_then(|n| <$t>::try_from(n).ok())
+                        .ok_or_else(|| format!("expected an integer of type {}", stringify!($t)))
+                }
+            }
+        )*
+    };
+}
+
+impl_json_integer!(i8, i16, i32, i64, u8, u16, u32, u64, usize, isize);
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> JsonValue {
+        match self {
+            Some(value) => value.to_json(),
+            None => JsonValue::Null,
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        match value {
+            JsonValue::Null => Ok(None),
+            value => T::from_json(value).map(Some),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        value
+            .as_array()
+            .ok_or_else(|| String::from("expected an array"))?
+            .iter()
+            .enumerate()
+            .map(|(i, value)| T::from_json(value).map_err(|e| format!("[{}]: {}", i, e)))
+            .collect()
+    }
+}
+
+impl<T: ToJson> ToJson for HashMap<String, T> {
+    fn to_json(&self) -> JsonValue {
+        JsonValue::Object(
+            self.iter()
+                .map(|(key, value)| (key.clone(), value.to_json()))
+                .collect(),
+        )
+    }
+}
+
+impl<T: FromJson> FromJson for HashMap<String, T> {
+    fn from_json(value: &JsonValue) -> Result<Self, String> {
+        value
+            .as_object()
+            .ok_or_else(|| String::from("expected an object"))?
+            .iter()
+            .map(|(key, value)| {
+                T::from_json(value)
+                    .map(|value| (key.clone(), value))
+                    .map_err(|e| format!("{}: {}", key, e))
+            })
+            .collect()
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn to_json(&self) -> JsonValue {
+        (**self).to_json()
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(s: &str) -> Self {
+        JsonValue::String(s.to_string())
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(s: String) -> Self {
+        JsonValue::String(s)
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(b: bool) -> Self {
+        JsonValue::Bool(b)
+    }
+}
+
+impl From<f64> for JsonValue {
+    fn from(n: f64) -> Self {
+        JsonValue::Number(JsonNumber::Float(n))
+    }
+}
+
+impl From<i64> for JsonValue {
+    fn from(n: i64) -> Self {
+        JsonValue::Number(JsonNumber::Integer(n.into()))
+    }
+}
+
+impl From<u64> for JsonValue {
+    fn from(n: u64) -> Self {
+        JsonValue::Number(JsonNumber::Integer(n.into()))
+    }
+}
+
+impl From<Vec<JsonValue>> for JsonValue {
+    fn from(array: Vec<JsonValue>) -> Self {
+        JsonValue::Array(array)
+    }
+}
+
+impl From<BTreeMap<String, JsonValue>> for JsonValue {
+    fn from(object: BTreeMap<String, JsonValue>) -> Self {
+        JsonValue::Object(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> JsonValue {
+        JsonValue::parse(input).unwrap()
+    }
+
+    fn error(input: &str) -> String {
+        JsonValue::parse(input).unwrap_err().message
+    }
+
+    #[test]
+    fn values_are_parsed() {
+        let value = parse(r#" {"a": [1, -2.5, true, false, null], "b": {"c": "d"}, "e": []} "#);
+        assert_eq!(value.get("a").unwrap().as_array().unwrap().len(), 5);
+        assert_eq!(
+            value.get("a").unwrap().as_array().unwrap()[1].as_f64(),
+            Some(-2.5)
+        );
+        assert!(value.get("a").unwrap().as_array().unwrap()[4].is_null());
+        assert_eq!(
+            value.get("b").unwrap().get("c").unwrap().as_str(),
+            Some("d")
+        );
+        assert_eq!(value.get("missing"), None);
+        assert_eq!(
+            value.to_string(),
+            r#"{"a":[1,-2.5,true,false,null],"b":{"c":"d"},"e":[]}"#
+        );
+    }
+
+    #[test]
+    fn pretty_output_is_indented() {
+        assert_eq!(
+            parse(r#"{"a":[1,{}],"b":"c"}"#).to_pretty_string(),
+            "{\n  \"a\": [\n    1,\n    {}\n  ],\n  \"b\": \"c\"\n}"
+        );
+    }
+
+    #[test]
+    fn string_escapes_round_trip() {
+        let value = parse(r#""quote \" backslash \\ slash \/ \b\f\n\r\t \u00e9 \u0001""#);
+        assert_eq!(
+            value.as_str(),
+            Some("quote \" backslash \\ slash / \u{8}\u{c}\n\r\t \u{e9} \u{1}")
+        );
+        assert_eq!(
+            value.to_string(),
+            r#""quote \" backslash \\ slash / \u0008\u000c\n\r\t é \u0001""#
+        );
+        assert_eq!(parse(&value.to_string()), value);
+    }
+
+    #[test]
+    fn surrogate_pairs_are_combined() {
+        assert_eq!(parse(r#""\ud83d\ude00""#).as_str(), Some("\u{1f600}"));
+        assert_eq!(parse("\"\u{1f600}\"").as_str(), Some("\u{1f600}"));
+        assert_eq!(error(r#""\ud83d""#), "Unpaired surrogate in string");
+        assert_eq!(
+            error(r#""\ud83d\u0041""#),
+            "Invalid low surrogate in string"
+        );
+        assert_eq!(error(r#""\ude00""#), "Invalid unicode escape");
+        assert_eq!(error(r#""\u+041""#), "Invalid unicode escape");
+        assert_eq!(error(r#""\u12""#), "Invalid unicode escape");
+    }
+
+    #[test]
+    fn numbers_are_parsed() {
+        assert_eq!(parse("0").as_i64(), Some(0));
+        assert_eq!(parse("-0").as_i64(), Some(0));
+        assert_eq!(parse("1.5e3").as_f64(), Some(1500.0));
+        assert_eq!(parse("1E-2").as_f64(), Some(0.01));
+        assert_eq!(parse("2.0").as_i64(), Some(2));
+        assert_eq!(parse("2.5").as_i64(), None);
+        assert_eq!(parse("1e300").to_string(), "1e300");
+        assert_eq!(parse("0.0000001").to_string(), "1e-7");
+        assert_eq!(parse("1.0"), parse("1"));
+        for invalid in ["01", "-", "1.", ".5", "1e", "+1", "0x10", "1.e5"] {
+            assert!(JsonValue::parse(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(JsonValue::from(f64::NAN).to_string(), "null");
+    }
+
+    #[test]
+    fn large_integers_are_exact() {
+        for n in [i64::MIN, i64::MAX, (1 << 53) + 1] {
+            let json = n.to_json().to_string();
+            assert_eq!(json, n.to_string());
+            assert_eq!(i64::from_json(&parse(&json)), Ok(n));
+        }
+        let json = u64::MAX.to_json().to_string();
+        assert_eq!(json, "18446744073709551615");
+        assert_eq!(u64::from_json(&parse(&json)), Ok(u64::MAX));
+        assert_eq!(
+            usize::from_json(&parse("9007199254740993")),
+            Ok(9_007_199_254_740_993)
+        );
+        assert!(i64::from_json(&parse("9223372036854775808")).is_err());
+        assert!(u8::from_json(&parse("256")).is_err());
+        assert!(u32::from_json(&parse("-1")).is_err());
+        // Floats past 2^53 are not exact, so they don't convert to integers.
+        assert!(i64::from_json(&parse("1e17")).is_err());
+        // Integers beyond i128 are kept as floats.
+        assert!(parse(&"9".repeat(40)).as_f64().unwrap() > 1e39);
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(JsonValue::parse(&nested(MAX_DEPTH + 1)).is_ok());
+        assert_eq!(error(&nested(MAX_DEPTH + 2)), "JSON nested too deeply");
+        assert_eq!(error(&"[".repeat(100_000)), "JSON nested too deeply");
+    }
+
+    #[test]
+    fn malformed_input_is_rejected_with_position() {
+        let cases = [
+            ("", "Unexpected end of input"),
+            ("[1,]", "Unexpected character"),
+            ("[1 2]", "Expected ',' or ']' in array"),
+            ("{\"a\" 1}", "Expected ':' after object key"),
+            ("{a: 1}", "Expected string key in object"),
+            ("{\"a\": 1,}", "Expected string key in object"),
+            ("\"abc", "Unterminated string"),
+            ("\"a\nb\"", "Control character in string"),
+            ("\"\\x\"", "Invalid escape sequence"),
+            ("tru", "Invalid literal"),
+            ("1 2", "Trailing characters after JSON value"),
+        ];
+        for (input, message) in cases {
+            assert_eq!(error(input), message, "{:?}", input);
+        }
+        let e = JsonValue::parse("{\n  \"a\": x\n}").unwrap_err();
+        assert_eq!((e.line, e.column), (2, 8));
+    }
+
+    #[test]
+    fn typed_conversions() {
+        let value = parse(r#"{"names": ["a", "b"], "age": null}"#);
+        let names = HashMap::<String, JsonValue>::from_json(&value).unwrap();
+        assert_eq!(
+            Vec::<String>::from_json(&names["names"]),
+            Ok(vec![String::from("a"), String::from("b")])
+        );
+        assert_eq!(Option::<u32>::from_json(&names["age"]), Ok(None));
+        assert_eq!(
+            Vec::<u32>::from_json(&parse(r#"[1, "x"]"#)),
+            Err(String::from("[1]: expected an integer of type u32"))
+        );
+        assert_eq!(vec![Some(1), None].to_json().to_string(), "[1,null]");
+    }
+}
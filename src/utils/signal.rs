@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Once;
+
+static INSTALL_HANDLERS: Once = Once::new();
+
+// Whether the handlers were installed, so signals are counted.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+// Number of shutdown signals (SIGINT and SIGTERM, or Ctrl+C and Ctrl+Break on Windows) received since the handlers were installed.
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(unix)]
+mod unix {
+    use std::os::raw::c_int;
+
+    pub const SIGINT: c_int = 2;
+    pub const SIGTERM: c_int = 15;
+    // What signal returns when the handler couldn't be installed, (sighandler_t)-1.
+    pub const SIG_ERR: usize = usize::MAX;
+
+    extern "C" {
+        pub fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    // Only touches an atomic, which is all a signal handler may safely do.
+    pub extern "C" fn on_signal(_signum: c_int) {
+        super::RECEIVED.fetch_add(1, super::Ordering::SeqCst);
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    pub const CTRL_C_EVENT: u32 = 0;
+    pub const CTRL_BREAK_EVENT: u32 = 1;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub fn SetConsoleCtrlHandler(
+            handler: Option<unsafe extern "system" fn(u32) -> i32>,
+            add: i32,
+        ) -> i32;
+    }
+
+    // Runs on a thread the system creates for the event. Other events, like closing the console window, keep their default handling.
+    pub unsafe extern "system" fn on_ctrl(ctrl_type: u32) -> i32 {
+        match ctrl_type {
+            CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+                super::RECEIVED.fetch_add(1, super::Ordering::SeqCst);
+                1
+            }
+            _ => 0,
+        }
+    }
+}
+
+// Install handlers that count SIGINT and SIGTERM (Ctrl+C and Ctrl+Break on Windows) instead of terminating the process. Returns false if shutdown signals aren't supported on this platform or the handlers couldn't be installed.
+pub fn install_shutdown_handlers() -> bool {
+    INSTALL_HANDLERS.call_once(|| {
+        #[cfg(unix)]
+        unsafe {
+            let installed = unix::signal(unix::SIGINT, unix::on_signal) != unix::SIG_ERR
+                && unix::signal(unix::SIGTERM, unix::on_signal) != unix::SIG_ERR;
+            INSTALLED.store(installed, Ordering::SeqCst);
+        }
+        #[cfg(windows)]
+        unsafe {
+            let added = windows::SetConsoleCtrlHandler(Some(windows::on_ctrl), 1) != 0;
+            INSTALLED.store(added, Ordering::SeqCst);
+        }
+    });
+    INSTALLED.load(Ordering::SeqCst)
+}
+
+// Number of shutdown signals received so far.
+pub fn shutdown_signals() -> usize {
+    RECEIVED.load(Ordering::SeqCst)
+}
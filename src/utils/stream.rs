@@ -1,20 +1,84 @@
-use std::error::Error;
-use std::io::{BufRead, BufReader};
+use std::cell::Cell;
+use std::io::{self, BufRead, Read};
 use std::net::TcpStream;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-pub fn read_stream_lines(stream: &TcpStream) -> Result<Vec<String>, Box<dyn Error>> {
-    let buf_reader = BufReader::new(stream);
-    let mut lines: Vec<String> = Vec::new();
-    for line in buf_reader.lines() {
-        match line {
-            Ok(line) => {
-                if line.is_empty() {
-                    break;
-                };
-                lines.push(line);
+// Read a line ending in LF and return it without the LF. A CR before the LF is left to the caller. Returns None if the line is longer than max bytes, leaving the rest of it in the reader.
+pub fn read_line_limited(reader: &mut dyn BufRead, max: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    // Room for the CRLF ending.
+    Read::take(&mut *reader, max as u64 + 2).read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\n") {
+        if line.len() > max {
+            return Ok(None);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Connection closed in the middle of a line",
+        ));
+    }
+    line.pop();
+    if line.len() > max + usize::from(line.ends_with(b"\r")) {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+// Timeout for the reads of a connection. Each read may wait up to per_read, but never past the deadline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadTimeout {
+    pub per_read: Option<Duration>,
+    pub deadline: Option<Instant>,
+}
+
+impl ReadTimeout {
+    pub fn new(per_read: Duration, deadline: Option<Instant>) -> Self {
+        Self {
+            per_read: Some(per_read),
+            deadline,
+        }
+    }
+}
+
+// Stream that applies a read timeout shared with the code serving the connection, so it can be changed between reading the head and the body of a request.
+pub struct TimedStream {
+    stream: TcpStream,
+    timeout: Rc<Cell<ReadTimeout>>,
+    // The timeout currently set on the socket, to avoid setting it again on every read.
+    applied: Option<Option<Duration>>,
+}
+
+impl TimedStream {
+    pub fn new(stream: TcpStream, timeout: Rc<Cell<ReadTimeout>>) -> Self {
+        Self {
+            stream,
+            timeout,
+            applied: None,
+        }
+    }
+}
+
+impl Read for TimedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = self.timeout.get();
+        let mut wait = timeout.per_read;
+        if let Some(deadline) = timeout.deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Read deadline passed",
+                ));
             }
-            Err(e) => return Err(Box::new(e)),
+            wait = Some(wait.map_or(left, |wait| wait.min(left)));
+        }
+        // A zero timeout is rejected by the socket.
+        let wait = wait.filter(|wait| !wait.is_zero());
+        if self.applied != Some(wait) {
+            self.stream.set_read_timeout(wait)?;
+            self.applied = Some(wait);
         }
+        self.stream.read(buf)
     }
-    Ok(lines)
 }
@@ -1,69 +1,984 @@
-use std::sync::mpsc::{self, Receiver};
-use std::sync::{Arc, Mutex};
+use std::collections::VecDeque;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
-// A thread pool that executes jobs in parallel threads.
+use crate::utils::general::panic_message;
+
+// How often workers are checked while waiting for them to finish with a timeout.
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// How many times an idle worker checks for new jobs before going to sleep.
+const SPIN_ATTEMPTS: usize = 16;
+
+// Size settings of a thread pool. The pool starts with min_threads workers, adds workers up to max_threads while jobs are waiting, and retires workers above min_threads that stay idle for idle_timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    pub min_threads: usize,
+    pub max_threads: usize,
+    pub idle_timeout: Duration,
+    // Maximum number of jobs waiting for a worker. execute blocks while the queue is full.
+    pub queue_capacity: usize,
+    // Workers take jobs in priority order, except that every starvation_limit-th job is taken lowest priority first, so a steady stream of higher priority jobs can't hold back the others forever.
+    pub starvation_limit: usize,
+    // Workers kept for the critical lane on top of max_threads, so critical jobs run even while every other worker is busy. They are started when a critical job is queued and retire after idle_timeout, whatever min_threads is.
+    pub critical_workers: usize,
+    // Worker threads are named this followed by a number, e.g. "tiny-http-worker-3".
+    pub thread_name: &'static str,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            min_threads: 5,
+            max_threads: 50,
+            idle_timeout: Duration::from_secs(60),
+            queue_capacity: 1024,
+            starvation_limit: 8,
+            critical_workers: 1,
+            thread_name: "tiny-http-worker",
+        }
+    }
+}
+
+impl PoolConfig {
+    // A pool with a fixed number of workers.
+    pub fn fixed(threads: usize) -> Self {
+        Self {
+            min_threads: threads,
+            max_threads: threads,
+            ..Default::default()
+        }
+    }
+}
+
+// Snapshot of a pool's activity, see ThreadPool::stats.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    // Worker threads alive, including the ones kept for the critical lane.
+    pub workers: usize,
+    // Workers running a job.
+    pub active: usize,
+    // Workers waiting for a job.
+    pub idle: usize,
+    // Jobs waiting for a worker.
+    pub queued: usize,
+    // Jobs run to completion or to a panic since the pool was created.
+    pub executed: u64,
+    // Jobs that panicked.
+    pub panicked: u64,
+}
+
+// Priority lane of a job. Workers take critical jobs first and bulk jobs last.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Priority {
+    // Health checks, admin endpoints and other jobs that must not wait behind the rest.
+    Critical,
+    #[default]
+    Normal,
+    // Slow jobs like large downloads, which can wait.
+    Bulk,
+}
+
+impl Priority {
+    // The lanes from highest to lowest priority.
+    pub const ALL: [Priority; 3] = [Priority::Critical, Priority::Normal, Priority::Bulk];
+
+    fn lane(self) -> usize {
+        self as usize
+    }
+}
+
+// A thread pool that executes jobs in parallel threads. Each worker has its own deque of jobs and steals from the others when it runs out, so workers don't all contend on one queue.
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    shared: Arc<Shared>,
+}
+
+// Handle for queuing jobs on a pool from other threads, including from inside its jobs.
+#[derive(Clone)]
+pub struct Spawner {
+    shared: Arc<Shared>,
+}
+
+// State shared between the pool and its workers.
+struct Shared {
+    // One set of deques per worker slot, with a deque for each priority lane. Workers take jobs from the front of their own deques and steal from the back of the others.
+    deques: Vec<Mutex<Lanes>>,
+    // Jobs queued and not yet taken by a worker. A place is reserved before a job is pushed to a deque.
+    pending: AtomicUsize,
+    // Deque the next job goes to. Jobs are spread over the deques in turn.
+    next_deque: AtomicUsize,
+    // Jobs pushed to each lane and not yet taken.
+    waiting: [AtomicUsize; LANES],
+    // Workers alive, including ones about to be spawned. Changed only while holding state.
+    workers: AtomicUsize,
+    // Workers asleep waiting for a job. Changed only while holding state.
+    idle: AtomicUsize,
+    // Workers looking for a job, from when they are spawned or finish a job until they take one or go to sleep. They are left to find new jobs rather than waking or adding workers.
+    searching: AtomicUsize,
+    // Workers running a job.
+    active: AtomicUsize,
+    // Workers kept for the critical lane that are alive, and the ones of those asleep. Changed only while holding state.
+    critical: AtomicUsize,
+    critical_idle: AtomicUsize,
+    executed: AtomicU64,
+    panicked: AtomicU64,
+    closed: AtomicBool,
+    // Held while workers go to sleep or retire, and while waking them, so wake-ups aren't lost.
+    state: Mutex<State>,
+    // Signalled when a job is queued or the pool shuts down.
+    available: Condvar,
+    // Signalled when a critical job is queued or the pool shuts down, for the workers kept for the critical lane.
+    critical_available: Condvar,
+    // Signalled when a job is taken from a full queue.
+    space: Condvar,
+    config: PoolConfig,
+}
+
+struct State {
+    // Which deques belong to a live worker.
+    slots: Vec<bool>,
+    next_id: usize,
+    threads: Vec<thread::JoinHandle<()>>,
 }
 
 impl ThreadPool {
+    // Create a pool with a fixed number of workers.
     pub fn new(size: usize) -> ThreadPool {
-        assert!(size > 0, "Thread pool size must be greater than 0.");
-        let mut workers = Vec::with_capacity(size);
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        for i in 0..size {
-            workers.push(Worker::new(i, Arc::clone(&receiver)));
-        }
-        ThreadPool {
-            workers,
-            sender: Some(sender),
+        Self::with_config(PoolConfig::fixed(size))
+    }
+
+    // Create a pool that grows and shrinks within the configured range.
+    pub fn with_config(config: PoolConfig) -> ThreadPool {
+        assert!(
+            config.max_threads > 0,
+            "Thread pool size must be greater than 0."
+        );
+        assert!(
+            config.min_threads <= config.max_threads,
+            "Thread pool minimum size must not exceed the maximum."
+        );
+        assert!(
+            config.queue_capacity > 0,
+            "Thread pool queue capacity must be greater than 0."
+        );
+        assert!(
+            config.starvation_limit > 0,
+            "Thread pool starvation limit must be greater than 0."
+        );
+        let shared = Arc::new(Shared {
+            deques: (0..config.max_threads)
+                .map(|_| Mutex::new(Default::default()))
+                .collect(),
+            pending: AtomicUsize::new(0),
+            next_deque: AtomicUsize::new(0),
+            waiting: Default::default(),
+            workers: AtomicUsize::new(0),
+            idle: AtomicUsize::new(0),
+            searching: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            critical: AtomicUsize::new(0),
+            critical_idle: AtomicUsize::new(0),
+            executed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            state: Mutex::new(State {
+                slots: vec![false; config.max_threads],
+                next_id: 0,
+                threads: Vec::with_capacity(config.min_threads),
+            }),
+            available: Condvar::new(),
+            critical_available: Condvar::new(),
+            space: Condvar::new(),
+            config,
+        });
+        let mut state = shared.state.lock().unwrap();
+        for _ in 0..config.min_threads {
+            shared.spawn_worker(&mut state, false);
         }
+        drop(state);
+        ThreadPool { shared }
     }
 
+    // Queue a job in the normal lane, waiting for room if the queue is full.
     pub fn execute<F>(&mut self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+        self.execute_with(Priority::Normal, f);
+    }
+
+    // Queue a job in the lane of the priority, waiting for room if the queue is full.
+    pub fn execute_with<F>(&mut self, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.assert_open();
+        if !self.shared.reserve() {
+            let mut state = self.shared.state.lock().unwrap();
+            while !self.shared.reserve() {
+                state = self.shared.space.wait(state).unwrap();
+            }
+        }
+        self.shared.push(priority, Box::new(f));
+    }
+
+    // Queue a job in the normal lane if there is room, or give it back if the queue is full.
+    pub fn try_execute<F>(&mut self, f: F) -> Result<(), F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_execute_with(Priority::Normal, f)
+    }
+
+    // Queue a job in the lane of the priority if there is room, or give it back if the queue is full.
+    pub fn try_execute_with<F>(&mut self, priority: Priority, f: F) -> Result<(), F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.assert_open();
+        if !self.shared.reserve() {
+            return Err(f);
+        }
+        self.shared.push(priority, Box::new(f));
+        Ok(())
+    }
+
+    fn assert_open(&self) {
+        assert!(
+            !self.shared.closed.load(Ordering::SeqCst),
+            "Thread pool is shut down."
+        );
+    }
+
+    // Get a handle for queuing jobs from other threads.
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    // Whether the queue is full, so execute would block and try_execute would fail.
+    pub fn is_full(&self) -> bool {
+        self.shared.pending.load(Ordering::SeqCst) >= self.shared.config.queue_capacity
+    }
+
+    // Number of worker threads alive, including the ones kept for the critical lane.
+    pub fn size(&self) -> usize {
+        self.shared.workers.load(Ordering::SeqCst) + self.shared.critical.load(Ordering::SeqCst)
+    }
+
+    // Number of jobs waiting for a worker.
+    pub fn queue_depth(&self) -> usize {
+        self.shared.pending.load(Ordering::SeqCst)
+    }
+
+    // Number of jobs waiting for a worker in the lane of the priority.
+    pub fn lane_depth(&self, priority: Priority) -> usize {
+        self.shared.waiting[priority.lane()].load(Ordering::SeqCst)
+    }
+
+    // Number of workers waiting for a job.
+    pub fn idle_workers(&self) -> usize {
+        self.shared.idle.load(Ordering::SeqCst)
+            + self.shared.searching.load(Ordering::SeqCst)
+            + self.shared.critical_idle.load(Ordering::SeqCst)
+    }
+
+    // Snapshot of the pool's activity. The counters are read one by one, so they may be slightly out of step with each other.
+    pub fn stats(&self) -> PoolStats {
+        self.shared.stats()
+    }
+
+    // Stop accepting jobs and wait for the workers to finish the queued ones. With a timeout, workers still busy when it passes are detached and false is returned.
+    pub fn shutdown(&mut self, timeout: Option<Duration>) -> bool {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        // No workers are added once the pool is closed, so the list of threads is final.
+        let threads = {
+            let mut state = self.shared.state.lock().unwrap();
+            self.shared.available.notify_all();
+            self.shared.critical_available.notify_all();
+            self.shared.space.notify_all();
+            mem::take(&mut state.threads)
+        };
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        for thread in threads {
+            // A pool dropped by one of its own jobs can't wait for the worker running it, which exits once the job returns.
+            if thread.thread().id() == thread::current().id() {
+                continue;
+            }
+            if let Some(deadline) = deadline {
+                while !thread.is_finished() && Instant::now() < deadline {
+                    thread::sleep(JOIN_POLL_INTERVAL);
+                }
+                if !thread.is_finished() {
+                    return false;
+                }
+            }
+            thread.join().unwrap();
+        }
+        true
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take().unwrap());
-        for worker in &mut self.workers {
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+        self.shutdown(None);
+    }
+}
+
+impl Spawner {
+    // Queue a job in the lane of the priority, waiting for room if the queue is full, or give it back if the pool is shut down.
+    pub fn execute_with<F>(&self, priority: Priority, f: F) -> Result<(), F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if !self.shared.reserve() {
+            let mut state = self.shared.state.lock().unwrap();
+            while !self.shared.reserve() {
+                // Shutdown wakes callers waiting for room, so they don't wait for workers that may never make it.
+                if self.shared.closed.load(Ordering::SeqCst) {
+                    return Err(f);
+                }
+                state = self.shared.space.wait(state).unwrap();
+            }
+        }
+        if self.shared.closed.load(Ordering::SeqCst) {
+            self.shared.release();
+            return Err(f);
+        }
+        self.shared.push(priority, Box::new(f));
+        Ok(())
+    }
+
+    // Queue a job in the lane of the priority, or give it back if the queue is full or the pool is shut down.
+    pub fn try_execute_with<F>(&self, priority: Priority, f: F) -> Result<(), F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if !self.shared.reserve() {
+            return Err(f);
+        }
+        // Checked after reserving, as a pool that closes later still runs the job.
+        if self.shared.closed.load(Ordering::SeqCst) {
+            self.shared.release();
+            return Err(f);
+        }
+        self.shared.push(priority, Box::new(f));
+        Ok(())
+    }
+}
+
+impl Spawner {
+    // Whether the queue is full, as ThreadPool::is_full.
+    pub fn is_full(&self) -> bool {
+        self.shared.pending.load(Ordering::SeqCst) >= self.shared.config.queue_capacity
+    }
+
+    // Snapshot of the pool's activity, as ThreadPool::stats.
+    pub fn stats(&self) -> PoolStats {
+        self.shared.stats()
+    }
+}
+
+impl Shared {
+    fn stats(&self) -> PoolStats {
+        PoolStats {
+            workers: self.workers.load(Ordering::Relaxed) + self.critical.load(Ordering::Relaxed),
+            active: self.active.load(Ordering::Relaxed),
+            idle: self.idle.load(Ordering::Relaxed)
+                + self.searching.load(Ordering::Relaxed)
+                + self.critical_idle.load(Ordering::Relaxed),
+            queued: self.pending.load(Ordering::Relaxed),
+            executed: self.executed.load(Ordering::Relaxed),
+            panicked: self.panicked.load(Ordering::Relaxed),
+        }
+    }
+
+    // Push a job whose place in the queue is reserved, then wake or add a worker to run it. Workers don't exit while a place is reserved, so the job runs even if the pool was closed meanwhile.
+    fn push(self: &Arc<Self>, priority: Priority, job: Job) {
+        let workers = self.workers.load(Ordering::SeqCst);
+        let next = self.next_deque.fetch_add(1, Ordering::Relaxed);
+        let slot = next % workers.clamp(1, self.deques.len());
+        // Counted before the push, so a worker taking the job right away can't bring the count below zero.
+        self.waiting[priority.lane()].fetch_add(1, Ordering::SeqCst);
+        self.deques[slot].lock().unwrap()[priority.lane()].push_back(job);
+        if priority == Priority::Critical && self.config.critical_workers > 0 {
+            self.wake_critical();
+        }
+
+        // Searching workers take jobs without being woken, so sleeping ones are only woken for the jobs beyond those. Searching is read before idle, as workers count themselves idle before they stop searching.
+        let pending = self.pending.load(Ordering::SeqCst);
+        let searching = self.searching.load(Ordering::SeqCst);
+        let idle = self.idle.load(Ordering::SeqCst);
+        if pending > searching && idle > 0 {
+            let _state = self.state.lock().unwrap();
+            self.available.notify_one();
+        }
+        // Add a worker if there are more jobs waiting than searching and idle workers to take them.
+        if pending > searching + idle && workers < self.config.max_threads {
+            let mut state = self.state.lock().unwrap();
+            if self.pending.load(Ordering::SeqCst)
+                > self.searching.load(Ordering::SeqCst) + self.idle.load(Ordering::SeqCst)
+                && self.workers.load(Ordering::SeqCst) < self.config.max_threads
+                && !self.closed.load(Ordering::SeqCst)
+            {
+                self.spawn_worker(&mut state, false);
             }
         }
     }
+
+    // Wake or add a worker kept for the critical lane for a critical job just pushed. The other workers take critical jobs too, whichever gets there first.
+    fn wake_critical(self: &Arc<Self>) {
+        if self.critical_idle.load(Ordering::SeqCst) > 0 {
+            let _state = self.state.lock().unwrap();
+            self.critical_available.notify_one();
+        } else if self.critical.load(Ordering::SeqCst) < self.config.critical_workers {
+            let mut state = self.state.lock().unwrap();
+            if self.critical_idle.load(Ordering::SeqCst) == 0
+                && self.critical.load(Ordering::SeqCst) < self.config.critical_workers
+                && !self.closed.load(Ordering::SeqCst)
+            {
+                self.spawn_worker(&mut state, true);
+            }
+        }
+    }
+
+    fn spawn_worker(self: &Arc<Self>, state: &mut State, critical: bool) {
+        // Threads of retired workers are finished, so their handles can go.
+        state.threads.retain(|thread| !thread.is_finished());
+        // Workers kept for the critical lane have no deque of their own, as jobs are only pushed to the others. They start looking for jobs at one of those.
+        let slot = if critical {
+            state.next_id % self.deques.len()
+        } else {
+            let Some(slot) = state.slots.iter().position(|taken| !taken) else {
+                return;
+            };
+            state.slots[slot] = true;
+            slot
+        };
+        state.next_id += 1;
+        if critical {
+            self.critical.fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.workers.fetch_add(1, Ordering::SeqCst);
+            // A new worker looks for a job before going to sleep.
+            self.searching.fetch_add(1, Ordering::SeqCst);
+        }
+        let worker = Worker {
+            id: state.next_id - 1,
+            slot,
+            critical,
+            shared: Arc::clone(self),
+        };
+        let spawned = thread::Builder::new()
+            .name(format!("{}-{}", self.config.thread_name, worker.id))
+            .spawn(move || worker.run());
+        match spawned {
+            Ok(thread) => state.threads.push(thread),
+            Err(e) => {
+                // The pool keeps working with the workers it has.
+                error!("Could not start a worker thread: {}", e);
+                if critical {
+                    self.critical.fetch_sub(1, Ordering::SeqCst);
+                } else {
+                    state.slots[slot] = false;
+                    self.searching.fetch_sub(1, Ordering::SeqCst);
+                    self.workers.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+        }
+    }
+
+    // Turn an idle worker back into a searching one. It counts as searching first, so push never sees it as neither and adds a worker for nothing.
+    fn wake(&self) {
+        self.searching.fetch_add(1, Ordering::SeqCst);
+        self.idle.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    // Release a place in the queue, waking a caller waiting for room if the queue was full.
+    fn release(&self) {
+        let pending = self.pending.fetch_sub(1, Ordering::SeqCst);
+        if pending >= self.config.queue_capacity {
+            let _state = self.state.lock().unwrap();
+            self.space.notify_one();
+        }
+    }
+
+    // Reserve a place in the queue, failing if it is full.
+    fn reserve(&self) -> bool {
+        self.pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                (pending < self.config.queue_capacity).then_some(pending + 1)
+            })
+            .is_ok()
+    }
 }
 
 // A worker thread that executes jobs.
 struct Worker {
-    _id: usize,
-    thread: Option<thread::JoinHandle<()>>,
+    id: usize,
+    // Index of the worker's own deque.
+    slot: usize,
+    // Whether the worker is kept for the critical lane and takes no other jobs.
+    critical: bool,
+    shared: Arc<Shared>,
 }
 
 impl Worker {
-    fn new(_id: usize, receiver: Arc<Mutex<Receiver<Job>>>) -> Worker {
-        Worker {
-            _id,
-            thread: Some(thread::spawn(move || loop {
-                match receiver.lock().unwrap().recv() {
-                    Ok(job) => {
-                        job();
-                    }
-                    Err(_) => {
-                        break;
-                    }
+    fn run(self) {
+        if self.critical {
+            return self.run_critical();
+        }
+        let config = self.shared.config;
+        let mut taken = 0usize;
+        loop {
+            let lowest_first = taken
+                .wrapping_add(1)
+                .is_multiple_of(config.starvation_limit);
+            if let Some((job, lane)) = self.find_job(lowest_first) {
+                self.take(lane);
+                taken = taken.wrapping_add(1);
+                self.shared.active.fetch_add(1, Ordering::Relaxed);
+                self.shared.searching.fetch_sub(1, Ordering::SeqCst);
+                self.run_job(job);
+                self.shared.searching.fetch_add(1, Ordering::SeqCst);
+                self.shared.active.fetch_sub(1, Ordering::Relaxed);
+                self.shared.executed.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            // Jobs often arrive just after the last one is taken, so look again a few times before paying for going to sleep and being woken up.
+            if (0..SPIN_ATTEMPTS).any(|_| {
+                thread::yield_now();
+                self.shared.pending.load(Ordering::SeqCst) > 0
+            }) {
+                continue;
+            }
+
+            let mut state = self.shared.state.lock().unwrap();
+            // Announce the worker as idle before it stops searching and checks for jobs, as push queues a job before checking for searching and idle workers. One of the two always sees the other.
+            self.shared.idle.fetch_add(1, Ordering::SeqCst);
+            self.shared.searching.fetch_sub(1, Ordering::SeqCst);
+            if self.shared.pending.load(Ordering::SeqCst) > 0 {
+                // A job is queued, or about to be pushed by execute.
+                self.shared.wake();
+                drop(state);
+                thread::yield_now();
+                continue;
+            }
+            if self.shared.closed.load(Ordering::SeqCst) {
+                self.shared.idle.fetch_sub(1, Ordering::SeqCst);
+                self.retire(&mut state);
+                return;
+            }
+
+            if self.shared.workers.load(Ordering::SeqCst) > config.min_threads {
+                let (guard, wait) = self
+                    .shared
+                    .available
+                    .wait_timeout(state, config.idle_timeout)
+                    .unwrap();
+                state = guard;
+                self.shared.wake();
+                // Retire workers above the minimum that had nothing to do for the whole idle timeout.
+                if wait.timed_out()
+                    && self.shared.pending.load(Ordering::SeqCst) == 0
+                    && self.shared.workers.load(Ordering::SeqCst) > config.min_threads
+                {
+                    self.shared.searching.fetch_sub(1, Ordering::SeqCst);
+                    self.retire(&mut state);
+                    return;
                 }
-            })),
+            } else {
+                let _state = self.shared.available.wait(state).unwrap();
+                self.shared.wake();
+            }
         }
     }
+
+    // Body of a worker kept for the critical lane. It sleeps right away when there are no critical jobs, as it isn't woken for the others.
+    fn run_critical(self) {
+        let lane = Priority::Critical.lane();
+        loop {
+            if let Some((job, lane)) = self.find_job(false) {
+                self.take(lane);
+                self.shared.active.fetch_add(1, Ordering::Relaxed);
+                self.run_job(job);
+                self.shared.active.fetch_sub(1, Ordering::Relaxed);
+                self.shared.executed.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+
+            let mut state = self.shared.state.lock().unwrap();
+            // Announce the worker as idle before checking for critical jobs, as push counts a critical job before checking for idle workers.
+            self.shared.critical_idle.fetch_add(1, Ordering::SeqCst);
+            if self.shared.waiting[lane].load(Ordering::SeqCst) > 0 {
+                self.shared.critical_idle.fetch_sub(1, Ordering::SeqCst);
+                drop(state);
+                thread::yield_now();
+                continue;
+            }
+            if self.shared.closed.load(Ordering::SeqCst) {
+                self.shared.critical_idle.fetch_sub(1, Ordering::SeqCst);
+                self.shared.critical.fetch_sub(1, Ordering::SeqCst);
+                return;
+            }
+            let (guard, wait) = self
+                .shared
+                .critical_available
+                .wait_timeout(state, self.shared.config.idle_timeout)
+                .unwrap();
+            state = guard;
+            self.shared.critical_idle.fetch_sub(1, Ordering::SeqCst);
+            if wait.timed_out() && self.shared.waiting[lane].load(Ordering::SeqCst) == 0 {
+                self.shared.critical.fetch_sub(1, Ordering::SeqCst);
+                drop(state);
+                return;
+            }
+        }
+    }
+
+    fn run_job(&self, job: Job) {
+        // A panicking job must not take the worker down with it, or the pool would shrink for good.
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+            self.shared.panicked.fetch_add(1, Ordering::Relaxed);
+            error!(
+                "Worker {} recovered from panic: {}",
+                self.id,
+                panic_message(&*payload)
+            );
+        }
+    }
+
+    // Take a job from the worker's own deques, or steal one from another worker, going through the lanes from the highest priority or from the lowest. Workers kept for the critical lane only look at that lane.
+    fn find_job(&self, lowest_first: bool) -> Option<(Job, usize)> {
+        if self.shared.pending.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        let mut lanes = Priority::ALL.map(Priority::lane);
+        if lowest_first {
+            lanes.reverse();
+        }
+        let count = self.shared.deques.len();
+        lanes
+            .into_iter()
+            .filter(|&lane| !self.critical || lane == Priority::Critical.lane())
+            .filter(|&lane| self.shared.waiting[lane].load(Ordering::SeqCst) > 0)
+            .find_map(|lane| {
+                if let Some(job) = self.shared.deques[self.slot].lock().unwrap()[lane].pop_front() {
+                    return Some((job, lane));
+                }
+                (1..count).find_map(|offset| {
+                    self.shared.deques[(self.slot + offset) % count]
+                        .lock()
+                        .unwrap()[lane]
+                        .pop_back()
+                        .map(|job| (job, lane))
+                })
+            })
+    }
+
+    // Count a job taken from a deque as no longer waiting.
+    fn take(&self, lane: usize) {
+        self.shared.waiting[lane].fetch_sub(1, Ordering::SeqCst);
+        self.shared.release();
+    }
+
+    fn retire(&self, state: &mut State) {
+        state.slots[self.slot] = false;
+        self.shared.workers.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
+
+const LANES: usize = Priority::ALL.len();
+
+type Lanes = [VecDeque<Job>; LANES];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::sync::Barrier;
+
+    // Poll until the condition holds, failing the test if it doesn't within a few seconds.
+    fn wait_until(what: &str, condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting until {}",
+                what
+            );
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn elastic(min_threads: usize, max_threads: usize) -> PoolConfig {
+        PoolConfig {
+            min_threads,
+            max_threads,
+            idle_timeout: Duration::from_millis(50),
+            ..PoolConfig::default()
+        }
+    }
+
+    #[test]
+    fn pool_grows_to_max_and_retires_idle_workers() {
+        let mut pool = ThreadPool::with_config(elastic(1, 4));
+        assert_eq!(pool.size(), 1);
+
+        // Each job waits for all of them to start, which needs a worker per job.
+        let barrier = Arc::new(Barrier::new(5));
+        for _ in 0..4 {
+            let barrier = barrier.clone();
+            pool.execute(move || {
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+        assert_eq!(pool.size(), 4);
+
+        // More blocked jobs than max_threads don't add workers.
+        let (release, released) = mpsc::channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        for _ in 0..8 {
+            let released = released.clone();
+            pool.execute(move || {
+                let _ = released.lock().unwrap().recv();
+            });
+        }
+        wait_until("all workers are busy", || pool.stats().active == 4);
+        assert_eq!(pool.size(), 4);
+        assert!(pool.queue_depth() >= 4);
+        for _ in 0..8 {
+            release.send(()).unwrap();
+        }
+
+        wait_until("idle workers retire", || pool.size() == 1);
+        wait_until("jobs are counted", || pool.stats().executed == 12);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(pool.size(), 1, "workers below the minimum must not retire");
+    }
+
+    #[test]
+    fn workers_looking_for_jobs_are_not_joined_by_new_ones() {
+        let mut pool = ThreadPool::with_config(elastic(1, 8));
+        // Each job is queued once the previous one is done, while the worker is still looking for the next one or asleep.
+        for round in 1..=200 {
+            pool.execute(|| {});
+            wait_until("the job runs", || pool.stats().executed == round);
+        }
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn idle_workers_steal_from_busy_ones() {
+        let mut pool = ThreadPool::new(2);
+        let (started, busy_name) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started
+                .send(thread::current().name().unwrap().to_owned())
+                .unwrap();
+            let _ = released.recv();
+        });
+        // The first two workers have the ids of their deques.
+        let busy_name = busy_name.recv_timeout(Duration::from_secs(5)).unwrap();
+        let busy_slot: usize = busy_name.rsplit('-').next().unwrap().parse().unwrap();
+
+        // Queue every job on the deque of the busy worker, so the other one only gets them by stealing.
+        let (ran, ran_on) = mpsc::channel();
+        for _ in 0..20 {
+            let ran = ran.clone();
+            pool.shared.next_deque.store(busy_slot, Ordering::SeqCst);
+            pool.execute(move || {
+                ran.send(thread::current().name().unwrap().to_owned())
+                    .unwrap()
+            });
+        }
+        for _ in 0..20 {
+            let name = ran_on.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_ne!(name, busy_name);
+        }
+        release.send(()).unwrap();
+        assert!(pool.shutdown(Some(Duration::from_secs(5))));
+    }
+
+    // Queue jobs recording their names behind a job that holds the only worker, then release it and return the order the jobs ran in.
+    fn run_order(config: PoolConfig, jobs: &[(Priority, &'static str)]) -> Vec<&'static str> {
+        let mut pool = ThreadPool::with_config(config);
+        let (release, released) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = released.recv();
+        });
+        wait_until("the worker is busy", || pool.stats().active == 1);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for &(priority, name) in jobs {
+            let order = order.clone();
+            pool.execute_with(priority, move || order.lock().unwrap().push(name));
+        }
+        release.send(()).unwrap();
+        pool.shutdown(None);
+        let order = order.lock().unwrap().clone();
+        order
+    }
+
+    #[test]
+    fn jobs_run_in_lane_order() {
+        let order = run_order(
+            PoolConfig {
+                critical_workers: 0,
+                ..PoolConfig::fixed(1)
+            },
+            &[
+                (Priority::Bulk, "bulk"),
+                (Priority::Normal, "normal 1"),
+                (Priority::Critical, "critical"),
+                (Priority::Normal, "normal 2"),
+            ],
+        );
+        assert_eq!(order, ["critical", "normal 1", "normal 2", "bulk"]);
+    }
+
+    #[test]
+    fn starvation_limit_lets_lower_lanes_through() {
+        let mut jobs = vec![(Priority::Bulk, "bulk")];
+        jobs.extend([(Priority::Critical, "critical"); 5]);
+        let order = run_order(
+            PoolConfig {
+                starvation_limit: 3,
+                critical_workers: 0,
+                ..PoolConfig::fixed(1)
+            },
+            &jobs,
+        );
+        // The job holding the worker was the first one taken, so the third is taken lowest lane first.
+        assert_eq!(
+            order,
+            ["critical", "bulk", "critical", "critical", "critical", "critical"]
+        );
+    }
+
+    #[test]
+    fn critical_jobs_run_while_every_other_worker_is_busy() {
+        let mut pool = ThreadPool::with_config(elastic(1, 1));
+        let (release, released) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = released.recv();
+        });
+        wait_until("the worker is busy", || pool.stats().active == 1);
+        assert_eq!(pool.size(), 1);
+
+        let normal_ran = Arc::new(AtomicBool::new(false));
+        let flag = normal_ran.clone();
+        pool.execute(move || flag.store(true, Ordering::SeqCst));
+        let (done, finished) = mpsc::channel();
+        pool.execute_with(Priority::Critical, move || done.send(()).unwrap());
+        finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(pool.size(), 2);
+        // The worker kept for the critical lane leaves the normal job to the busy one.
+        thread::sleep(Duration::from_millis(20));
+        assert!(!normal_ran.load(Ordering::SeqCst));
+
+        release.send(()).unwrap();
+        wait_until("the normal job runs", || normal_ran.load(Ordering::SeqCst));
+        wait_until("the critical worker retires", || pool.size() == 1);
+    }
+
+    #[test]
+    fn shutdown_runs_queued_jobs() {
+        let mut pool = ThreadPool::new(2);
+        let count = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100 {
+            let count = count.clone();
+            pool.execute(move || {
+                thread::sleep(Duration::from_micros(100));
+                count.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        assert!(pool.shutdown(Some(Duration::from_secs(5))));
+        assert_eq!(count.load(Ordering::SeqCst), 100);
+        assert_eq!(pool.size(), 0);
+    }
+
+    #[test]
+    fn shutdown_gives_up_after_the_timeout() {
+        let mut pool = ThreadPool::new(1);
+        let (release, released) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = released.recv();
+        });
+        wait_until("the job starts", || pool.stats().active == 1);
+        let start = Instant::now();
+        assert!(!pool.shutdown(Some(Duration::from_millis(50))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Thread pool is shut down.")]
+    fn execute_after_shutdown_panics() {
+        let mut pool = ThreadPool::new(1);
+        pool.shutdown(None);
+        pool.execute(|| {});
+    }
+
+    #[test]
+    fn panicking_jobs_keep_the_worker() {
+        let mut pool = ThreadPool::new(1);
+        pool.execute(|| panic!("job panic"));
+        let (done, finished) = mpsc::channel();
+        pool.execute(move || done.send(()).unwrap());
+        finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        wait_until("the panic is counted", || pool.stats().executed == 2);
+        assert_eq!(pool.stats().panicked, 1);
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn full_queue_rejects_try_execute_and_blocks_execute() {
+        let mut pool = ThreadPool::with_config(PoolConfig {
+            queue_capacity: 2,
+            ..PoolConfig::fixed(1)
+        });
+        let (release, released) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = released.recv();
+        });
+        wait_until("the worker is busy", || pool.stats().active == 1);
+        let ran = Arc::new(AtomicUsize::new(0));
+        for _ in 0..2 {
+            let ran = ran.clone();
+            assert!(pool
+                .try_execute(move || {
+                    ran.fetch_add(1, Ordering::SeqCst);
+                })
+                .is_ok());
+        }
+        assert!(pool.is_full());
+        assert!(pool.try_execute(|| {}).is_err());
+        let spawner = pool.spawner();
+        assert!(spawner.try_execute_with(Priority::Critical, || {}).is_err());
+
+        // execute waits for room, which the worker makes once it is released.
+        let (queued, was_queued) = mpsc::channel();
+        let blocked = thread::spawn(move || {
+            let ran_later = ran.clone();
+            pool.execute(move || {
+                ran_later.fetch_add(1, Ordering::SeqCst);
+            });
+            queued.send(()).unwrap();
+            pool.shutdown(None);
+            ran.load(Ordering::SeqCst)
+        });
+        assert!(was_queued.recv_timeout(Duration::from_millis(100)).is_err());
+        release.send(()).unwrap();
+        was_queued.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(blocked.join().unwrap(), 3);
+    }
+}
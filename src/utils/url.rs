@@ -0,0 +1,77 @@
+use std::string::FromUtf8Error;
+
+// Decode a percent-encoded component. Invalid escapes are kept as they are, like browsers do.
+pub fn percent_decode(input: &str) -> Result<String, FromUtf8Error> {
+    decode(input, false)
+}
+
+// Decode a component of an application/x-www-form-urlencoded body, where '+' stands for a space.
+pub fn form_decode(input: &str) -> Result<String, FromUtf8Error> {
+    decode(input, true)
+}
+
+fn decode(input: &str, plus_as_space: bool) -> Result<String, FromUtf8Error> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => match (
+                bytes.get(i + 1).and_then(|&b| hex_value(b)),
+                bytes.get(i + 2).and_then(|&b| hex_value(b)),
+            ) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b'+' if plus_as_space => decoded.push(b' '),
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(decoded)
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_escapes_are_decoded() {
+        assert_eq!(percent_decode("a%20b%2Fc").unwrap(), "a b/c");
+        assert_eq!(percent_decode("%e2%82%ac").unwrap(), "\u{20ac}");
+        assert_eq!(percent_decode("no escapes").unwrap(), "no escapes");
+        assert_eq!(percent_decode("").unwrap(), "");
+    }
+
+    #[test]
+    fn plus_is_a_space_only_in_forms() {
+        assert_eq!(percent_decode("a+b").unwrap(), "a+b");
+        assert_eq!(form_decode("a+b%2B").unwrap(), "a b+");
+    }
+
+    #[test]
+    fn invalid_escapes_are_kept() {
+        assert_eq!(percent_decode("100%").unwrap(), "100%");
+        assert_eq!(percent_decode("%4").unwrap(), "%4");
+        assert_eq!(percent_decode("%zz%41").unwrap(), "%zzA");
+        assert_eq!(percent_decode("%%41").unwrap(), "%A");
+    }
+
+    #[test]
+    fn invalid_utf8_is_an_error() {
+        assert!(percent_decode("%ff").is_err());
+        assert!(form_decode("%e2%82").is_err());
+    }
+}
@@ -0,0 +1,713 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tiny_rust_server::communication::extract::Rejection;
+use tiny_rust_server::communication::form::Form;
+use tiny_rust_server::communication::json::Json;
+use tiny_rust_server::communication::multipart::MultipartError;
+use tiny_rust_server::communication::protocol::RequestLimits;
+use tiny_rust_server::communication::request::Request;
+use tiny_rust_server::communication::router::Router;
+use tiny_rust_server::log::logger::LogDestination;
+use tiny_rust_server::server::handle::ServerHandle;
+use tiny_rust_server::server::{KeepAlive, OverloadPolicy, Server, ServerBuilder, Timeouts};
+use tiny_rust_server::utils::json::JsonValue;
+use tiny_rust_server::utils::thread_pool::{PoolConfig, Priority};
+
+// Start a server on a free port with routes that answer with their own path.
+fn start() -> ServerHandle {
+    start_with(|builder| builder)
+}
+
+// Start the same server with settings changed by configure.
+fn start_with(configure: impl FnOnce(ServerBuilder) -> ServerBuilder) -> ServerHandle {
+    let builder = Server::builder()
+        .bind("127.0.0.1:0")
+        .log_destination(LogDestination::Disabled);
+    let mut server = configure(builder).build().unwrap();
+    for path in ["a", "b", "c"] {
+        let mut router = Router::new(&format!("/{}", path));
+        router.route("", "GET", move |_, response| response.set_content(path));
+        server.router(router);
+    }
+    // Answers without reading the body, which the server then has to skip.
+    let mut router = Router::new("/upload");
+    router.route("", "POST", |_, response| response.set_content("ignored"));
+    server.router(router);
+    // Answers with the body it read, or the status for the error reading it.
+    let mut router = Router::new("/echo");
+    router.route("", "POST", |request, response| match request.body() {
+        Ok(body) => response.set_content(&String::from_utf8_lossy(&body)),
+        Err(e) => Rejection::from(e).apply(response),
+    });
+    server.router(router);
+    // Answers with the fields of a form, sorted by name.
+    let mut router = Router::new("/form");
+    router.route_with(
+        "",
+        "POST",
+        |_, response, Form(fields): Form<HashMap<String, String>>| {
+            let mut fields: Vec<String> = fields
+                .into_iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            fields.sort();
+            response.set_content(&fields.join(" "));
+        },
+    );
+    server.router(router);
+    // Answers with the JSON body it was sent.
+    let mut router = Router::new("/json");
+    router.route_with("", "POST", |_, response, Json(value): Json<JsonValue>| {
+        response.json(&value)
+    });
+    server.router(router);
+    // Refuses every request, without reading the body.
+    let mut router = Router::new("/reject");
+    router.route("", "POST", |_, response| {
+        response.set_status(403, "Forbidden")
+    });
+    server.router(router);
+    let mut router = Router::new("/panic");
+    router.route("", "GET", |_, _| panic!("route failed"));
+    server.router(router);
+    let mut router = Router::new("/files");
+    router.route("", "POST", |request, response| {
+        match describe_parts(request) {
+            Ok(parts) => response.set_content(&parts),
+            Err(rejection) => rejection.apply(response),
+        }
+    });
+    server.router(router);
+    server.spawn().unwrap()
+}
+
+// One line per part of a multipart body, with its name, file name and content.
+fn describe_parts(request: &Request) -> Result<String, Rejection> {
+    let mut lines = Vec::new();
+    for part in request.multipart()? {
+        let part = part?;
+        let content = part.text().map_err(MultipartError::from)?;
+        lines.push(format!(
+            "{} {} {}",
+            part.name.unwrap_or_default(),
+            part.filename.unwrap_or_default(),
+            content
+        ));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn connect(handle: &ServerHandle) -> BufReader<TcpStream> {
+    let stream = TcpStream::connect(handle.local_addr()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    BufReader::new(stream)
+}
+
+struct Response {
+    status: u16,
+    connection: String,
+    content_type: String,
+    body: String,
+}
+
+fn read_response(reader: &mut BufReader<TcpStream>) -> Response {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let status = line.split(' ').nth(1).unwrap().parse().unwrap();
+    let mut connection = String::new();
+    let mut content_type = String::new();
+    let mut length = 0;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(": ").unwrap();
+        if name.eq_ignore_ascii_case("Connection") {
+            connection = value.to_string();
+        } else if name.eq_ignore_ascii_case("Content-Type") {
+            content_type = value.to_string();
+        } else if name.eq_ignore_ascii_case("Content-Length") {
+            length = value.parse().unwrap();
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    Response {
+        status,
+        connection,
+        content_type,
+        body: String::from_utf8(body).unwrap(),
+    }
+}
+
+fn send(reader: &mut BufReader<TcpStream>, raw: &str) {
+    reader.get_mut().write_all(raw.as_bytes()).unwrap();
+}
+
+// Whether the server closed the connection.
+fn is_closed(reader: &mut BufReader<TcpStream>) -> bool {
+    matches!(reader.read(&mut [0; 1]), Ok(0))
+}
+
+#[test]
+fn keep_alive_serves_several_requests_on_one_connection() {
+    let handle = start();
+    let mut client = connect(&handle);
+    for path in ["a", "b"] {
+        send(
+            &mut client,
+            &format!("GET /{} HTTP/1.1\r\nHost: x\r\n\r\n", path),
+        );
+        let response = read_response(&mut client);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.connection, "keep-alive");
+        assert_eq!(response.body, path);
+    }
+    send(
+        &mut client,
+        "GET /c HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+    );
+    let response = read_response(&mut client);
+    assert_eq!(response.connection, "close");
+    assert_eq!(response.body, "c");
+    assert!(is_closed(&mut client));
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn http_1_0_closes_unless_asked_to_keep_alive() {
+    let handle = start();
+    let mut client = connect(&handle);
+    send(
+        &mut client,
+        "GET /a HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+    );
+    assert_eq!(read_response(&mut client).connection, "keep-alive");
+    send(&mut client, "GET /b HTTP/1.0\r\n\r\n");
+    let response = read_response(&mut client);
+    assert_eq!(response.connection, "close");
+    assert_eq!(response.body, "b");
+    assert!(is_closed(&mut client));
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn pipelined_requests_are_answered_in_order() {
+    let handle = start();
+    let mut client = connect(&handle);
+    send(
+        &mut client,
+        "GET /c HTTP/1.1\r\nHost: x\r\n\r\n\
+         POST /upload HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello\
+         GET /a HTTP/1.1\r\nHost: x\r\n\r\n\
+         GET /b HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+    );
+    let responses: Vec<_> = (0..4).map(|_| read_response(&mut client)).collect();
+    let bodies: Vec<_> = responses.iter().map(|r| r.body.as_str()).collect();
+    assert_eq!(bodies, ["c", "ignored", "a", "b"]);
+    assert!(responses[..3].iter().all(|r| r.connection == "keep-alive"));
+    assert_eq!(responses[3].connection, "close");
+    assert!(is_closed(&mut client));
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn unread_body_too_large_to_skip_closes_the_connection() {
+    let handle = start();
+    let mut client = connect(&handle);
+    let body = "x".repeat(200 * 1024);
+    send(
+        &mut client,
+        &format!(
+            "POST /upload HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        ),
+    );
+    // The server answers without reading the body, so it may still be sending while the response comes back.
+    let mut writer = client.get_ref().try_clone().unwrap();
+    let sender = thread::spawn(move || {
+        let _ = writer.write_all(body.as_bytes());
+    });
+    let response = read_response(&mut client);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.connection, "close");
+    assert_eq!(response.body, "ignored");
+    assert!(is_closed(&mut client));
+    sender.join().unwrap();
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn invalid_pool_settings_are_rejected() {
+    let build = |pool: PoolConfig| {
+        Server::builder()
+            .bind("127.0.0.1:0")
+            .log_destination(LogDestination::Disabled)
+            .pool(pool)
+            .build()
+    };
+    assert!(build(PoolConfig::fixed(0)).is_err());
+    assert!(build(PoolConfig {
+        min_threads: 3,
+        max_threads: 2,
+        ..PoolConfig::default()
+    })
+    .is_err());
+    assert!(build(PoolConfig {
+        queue_capacity: 0,
+        ..PoolConfig::default()
+    })
+    .is_err());
+    assert!(build(PoolConfig {
+        starvation_limit: 0,
+        ..PoolConfig::default()
+    })
+    .is_err());
+    assert!(build(PoolConfig::fixed(1)).is_ok());
+}
+
+#[test]
+fn critical_routes_are_served_while_every_worker_is_busy() {
+    let mut server = Server::builder()
+        .bind("127.0.0.1:0")
+        .log_destination(LogDestination::Disabled)
+        .threads(1)
+        .build()
+        .unwrap();
+    let (release, released) = mpsc::channel::<()>();
+    let released = Mutex::new(released);
+    let mut slow = Router::new("/slow");
+    slow.route("", "GET", move |_, response| {
+        let _ = released.lock().unwrap().recv();
+        response.set_content("slow");
+    });
+    server.router(slow);
+    let mut health = Router::new("/health");
+    health.route("", "GET", |_, response| response.set_content("ok"));
+    server.router(health.clone());
+    // Given after the router is registered, which the server still sees when it starts.
+    health.route_priority("", "GET", Priority::Critical);
+    let handle = server.spawn().unwrap();
+
+    let mut busy = connect(&handle);
+    send(&mut busy, "GET /slow HTTP/1.1\r\nHost: x\r\n\r\n");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while handle.stats().pool.active == 0 {
+        assert!(Instant::now() < deadline, "the slow route didn't start");
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    // A client slow to send its request line still gets the lane of its route.
+    let mut client = connect(&handle);
+    thread::sleep(Duration::from_millis(30));
+    send(
+        &mut client,
+        "GET /health HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(read_response(&mut client).body, "ok");
+
+    release.send(()).unwrap();
+    assert_eq!(read_response(&mut busy).body, "slow");
+    drop(busy);
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+// Send a raw request on a new connection and return the response, checking the server closes the connection after it.
+fn error_response(handle: &ServerHandle, raw: &str) -> Response {
+    let mut client = connect(handle);
+    send(&mut client, raw);
+    let response = read_response(&mut client);
+    assert_eq!(response.connection, "close");
+    assert!(is_closed(&mut client));
+    response
+}
+
+#[test]
+fn protocol_errors_are_answered_with_their_status() {
+    let handle = start_with(|builder| {
+        builder.limits(RequestLimits {
+            max_target_length: 100,
+            max_header_size: 1000,
+            max_headers: 10,
+            max_body_size: 100,
+        })
+    });
+    let too_large = "POST /upload HTTP/1.1\r\nHost: x\r\nContent-Length: 101\r\n\r\n";
+    assert_eq!(error_response(&handle, too_large).status, 413);
+    let long_target = format!("GET /{} HTTP/1.1\r\nHost: x\r\n\r\n", "a".repeat(100));
+    assert_eq!(error_response(&handle, &long_target).status, 414);
+    let large_header = format!(
+        "GET /a HTTP/1.1\r\nHost: x\r\nX: {}\r\n\r\n",
+        "a".repeat(1000)
+    );
+    assert_eq!(error_response(&handle, &large_header).status, 431);
+    let many_headers = format!(
+        "GET /a HTTP/1.1\r\nHost: x\r\n{}\r\n",
+        "X: a\r\n".repeat(10)
+    );
+    assert_eq!(error_response(&handle, &many_headers).status, 431);
+    assert_eq!(
+        error_response(&handle, "GET /a HTTP/2.0\r\nHost: x\r\n\r\n").status,
+        505
+    );
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn request_head_not_sent_in_time_gets_408() {
+    let handle = start_with(|builder| {
+        builder.timeouts(Timeouts {
+            header_total: Duration::from_millis(200),
+            ..Timeouts::default()
+        })
+    });
+    let mut client = connect(&handle);
+    send(&mut client, "GET /a HTTP/1.1\r\n");
+    let response = read_response(&mut client);
+    assert_eq!(response.status, 408);
+    assert_eq!(response.connection, "close");
+    assert!(is_closed(&mut client));
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+// Poll until the condition holds, failing the test if it doesn't within the time.
+fn wait_until(what: &str, within: Duration, condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + within;
+    while !condition() {
+        assert!(
+            Instant::now() < deadline,
+            "timed out waiting until {}",
+            what
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn trickling_client_does_not_hold_the_worker_after_408() {
+    let handle = start_with(|builder| {
+        builder.threads(1).timeouts(Timeouts {
+            header_total: Duration::from_millis(300),
+            ..Timeouts::default()
+        })
+    });
+    let mut client = connect(&handle);
+    send(&mut client, "GET /a HTTP/1.1\r\nX: ");
+    // Keeps sending a byte at a time, well past the header deadline and the time lingering may take.
+    let mut writer = client.get_ref().try_clone().unwrap();
+    let trickle = thread::spawn(move || {
+        for _ in 0..40 {
+            if writer.write_all(b"a").is_err() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    });
+    let sent = Instant::now();
+    assert_eq!(read_response(&mut client).status, 408);
+    assert!(sent.elapsed() < Duration::from_secs(2));
+    wait_until("the worker is free", Duration::from_secs(2), || {
+        handle.stats().pool.active == 0
+    });
+
+    let mut other = connect(&handle);
+    send(
+        &mut other,
+        "GET /b HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(read_response(&mut other).body, "b");
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+    trickle.join().unwrap();
+}
+
+#[test]
+fn idle_keep_alive_connections_are_closed() {
+    let handle = start_with(|builder| {
+        builder.keep_alive(KeepAlive {
+            idle_timeout: Duration::from_millis(200),
+            ..KeepAlive::default()
+        })
+    });
+    let mut client = connect(&handle);
+    send(&mut client, "GET /a HTTP/1.1\r\nHost: x\r\n\r\n");
+    assert_eq!(read_response(&mut client).connection, "keep-alive");
+    let idle = Instant::now();
+    assert!(is_closed(&mut client));
+    assert!(idle.elapsed() >= Duration::from_millis(150));
+    assert!(idle.elapsed() < Duration::from_secs(2));
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn body_not_sent_in_time_gets_408() {
+    let handle = start_with(|builder| {
+        builder.timeouts(Timeouts {
+            body_read: Duration::from_millis(200),
+            ..Timeouts::default()
+        })
+    });
+    let mut client = connect(&handle);
+    send(
+        &mut client,
+        "POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 10\r\n\r\nabc",
+    );
+    let sent = Instant::now();
+    let response = read_response(&mut client);
+    assert_eq!(response.status, 408);
+    assert_eq!(response.connection, "close");
+    assert!(sent.elapsed() < Duration::from_secs(2));
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn multipart_uploads_are_parsed() {
+    let handle = start();
+    let mut client = connect(&handle);
+    let body = "--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        notes\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"upload\"; filename=\"notes.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        first line\r\nsecond line\r\n\
+        --XyZ--\r\n";
+    send(
+        &mut client,
+        &format!(
+            "POST /files HTTP/1.1\r\nHost: x\r\nContent-Type: multipart/form-data; boundary=XyZ\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+    );
+    let response = read_response(&mut client);
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.body,
+        "title  notes\nupload notes.txt first line\r\nsecond line"
+    );
+
+    // A body that isn't multipart is refused.
+    send(
+        &mut client,
+        "POST /files HTTP/1.1\r\nHost: x\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\nhi",
+    );
+    assert_eq!(read_response(&mut client).status, 415);
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn panicking_route_gets_500_and_the_worker_keeps_serving() {
+    let handle = start_with(|builder| builder.threads(1));
+    let mut client = connect(&handle);
+    send(&mut client, "GET /panic HTTP/1.1\r\nHost: x\r\n\r\n");
+    let response = read_response(&mut client);
+    assert_eq!(response.status, 500);
+    assert_eq!(response.connection, "close");
+    assert!(is_closed(&mut client));
+    assert_eq!(handle.stats().route_panics, 1);
+
+    // The only worker caught the panic and serves the next connection.
+    let mut client = connect(&handle);
+    send(&mut client, "GET /a HTTP/1.1\r\nHost: x\r\n\r\n");
+    let response = read_response(&mut client);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "a");
+    let stats = handle.stats();
+    assert_eq!(stats.route_panics, 1);
+    assert_eq!(stats.pool.panicked, 0);
+    drop(client);
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn continue_is_sent_once_the_route_reads_the_body() {
+    let handle = start();
+    let mut client = connect(&handle);
+    send(
+        &mut client,
+        "POST /echo HTTP/1.1\r\nHost: x\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n",
+    );
+    let interim = read_response(&mut client);
+    assert_eq!(interim.status, 100);
+    send(&mut client, "hello");
+    let response = read_response(&mut client);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "hello");
+    assert_eq!(response.connection, "keep-alive");
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn requests_answered_without_reading_the_body_get_no_continue() {
+    let handle = start();
+    for (path, status) in [("/upload", 200), ("/reject", 403)] {
+        let mut client = connect(&handle);
+        send(
+            &mut client,
+            &format!(
+                "POST {} HTTP/1.1\r\nHost: x\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n",
+                path
+            ),
+        );
+        // The client never sent the body, so the connection can't be reused.
+        let response = read_response(&mut client);
+        assert_eq!(response.status, status);
+        assert_eq!(response.connection, "close");
+        assert!(is_closed(&mut client));
+    }
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn unknown_expectations_get_417() {
+    let handle = start();
+    let response = error_response(
+        &handle,
+        "POST /echo HTTP/1.1\r\nHost: x\r\nExpect: 200-ok\r\nContent-Length: 5\r\n\r\nhello",
+    );
+    assert_eq!(response.status, 417);
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn stats_count_connections() {
+    let handle = start_with(|builder| {
+        builder
+            .pool(PoolConfig {
+                queue_capacity: 1,
+                critical_workers: 0,
+                ..PoolConfig::fixed(1)
+            })
+            .overload(OverloadPolicy::Reject {
+                retry_after: Duration::from_secs(1),
+            })
+    });
+    let within = Duration::from_secs(5);
+
+    // The only worker serves this connection until the client closes it.
+    let mut served = connect(&handle);
+    send(&mut served, "GET /a HTTP/1.1\r\nHost: x\r\n\r\n");
+    assert_eq!(read_response(&mut served).body, "a");
+    // This one waits in the queue, filling it.
+    let mut queued = connect(&handle);
+    send(&mut queued, "GET /b HTTP/1.1\r\nHost: x\r\n\r\n");
+    wait_until("the connection is queued", within, || {
+        handle.stats().pool.queued == 1
+    });
+    // And this one is shed.
+    let mut shed = connect(&handle);
+    send(&mut shed, "GET /c HTTP/1.1\r\nHost: x\r\n\r\n");
+    assert_eq!(read_response(&mut shed).status, 503);
+
+    let stats = handle.stats();
+    assert_eq!(stats.accepted, 3);
+    assert_eq!(stats.open_connections, 1);
+    assert_eq!(stats.shed, 1);
+    assert_eq!(stats.route_panics, 0);
+
+    // The panic closes the first connection, so the worker moves on to the queued one.
+    send(&mut served, "GET /panic HTTP/1.1\r\nHost: x\r\n\r\n");
+    assert_eq!(read_response(&mut served).status, 500);
+    assert_eq!(read_response(&mut queued).body, "b");
+    let stats = handle.stats();
+    assert_eq!(stats.route_panics, 1);
+    assert_eq!(stats.open_connections, 1);
+
+    drop(queued);
+    wait_until("every connection is closed", within, || {
+        handle.stats().open_connections == 0
+    });
+    let stats = handle.stats();
+    assert_eq!(stats.accepted, 3);
+    assert_eq!(stats.shed, 1);
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn form_extractor_reads_urlencoded_bodies() {
+    let handle = start();
+    let mut client = connect(&handle);
+    let body = "name=two+words&city=S%C3%A3o+Paulo";
+    send(
+        &mut client,
+        &format!(
+            "POST /form HTTP/1.1\r\nHost: x\r\nContent-Type: application/x-www-form-urlencoded; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+    );
+    let response = read_response(&mut client);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "city=S\u{e3}o Paulo name=two words");
+
+    // Other content types are refused without calling the route.
+    send(
+        &mut client,
+        "POST /form HTTP/1.1\r\nHost: x\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}",
+    );
+    let response = read_response(&mut client);
+    assert_eq!(response.status, 415);
+    assert_eq!(
+        response.body,
+        "Expected Content-Type application/x-www-form-urlencoded"
+    );
+    send(
+        &mut client,
+        "POST /form HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\na=1",
+    );
+    assert_eq!(read_response(&mut client).status, 415);
+
+    send(
+        &mut client,
+        "POST /form HTTP/1.1\r\nHost: x\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 8\r\n\r\nname=%ff",
+    );
+    assert_eq!(read_response(&mut client).status, 400);
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn json_bodies_are_parsed_and_sent() {
+    let handle = start();
+    let mut client = connect(&handle);
+    let body = r#"{"name": "tiny", "tags": [1, true, null]}"#;
+    send(
+        &mut client,
+        &format!(
+            "POST /json HTTP/1.1\r\nHost: x\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+    );
+    let response = read_response(&mut client);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.content_type, "application/json");
+    assert_eq!(response.body, r#"{"name":"tiny","tags":[1,true,null]}"#);
+
+    send(
+        &mut client,
+        "POST /json HTTP/1.1\r\nHost: x\r\nContent-Type: application/json\r\nContent-Length: 9\r\n\r\n{\"a\": 1,}",
+    );
+    let response = read_response(&mut client);
+    assert_eq!(response.status, 400);
+    assert!(response.body.starts_with("Invalid JSON: "));
+
+    send(
+        &mut client,
+        "POST /json HTTP/1.1\r\nHost: x\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\n{}",
+    );
+    let response = read_response(&mut client);
+    assert_eq!(response.status, 415);
+    assert_eq!(response.body, "Expected Content-Type application/json");
+    handle.shutdown_timeout(Duration::from_secs(5)).unwrap();
+}